@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::vault::SecretPolicy;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    pub(crate) template_repo: Option<String>,
+    pub(crate) spec_questions_path: Option<String>,
+    #[serde(default)]
+    pub(crate) secret_policies: BTreeMap<String, SecretPolicy>,
+    #[serde(default)]
+    pub(crate) secret_policy_patterns: BTreeMap<String, String>,
+    /// Number of previous vault file versions kept as `.<name>.bak.<n>`, none by default.
+    #[serde(default)]
+    pub(crate) vault_backups: usize,
+    /// Inventory used to pick the group or host of a secret, `inventory` of `ansible.cfg` by default.
+    pub(crate) inventory: Option<String>,
+    /// Keep when secrets were created, rotated and expire in a `<name>.meta.yml` next to each vault file.
+    #[serde(default)]
+    pub(crate) secret_metadata: bool,
+}
+
+/// Reads `./.tdtk.toml`, falling back to `~/.config/tdtk.toml`.
+pub fn load_config() -> Result<Config> {
+    let home_dir = dirs::home_dir().unwrap();
+    let cwd = std::env::current_dir()?;
+    let config_path_home = format!("{}/.config/tdtk.toml", home_dir.to_str().unwrap());
+    let config_path_cwd = format!("{}/.tdtk.toml", cwd.to_str().unwrap());
+
+    match fs::read_to_string(&config_path_cwd).or(fs::read_to_string(&config_path_home)) {
+        Ok(toml_str) => toml::from_str(toml_str.as_str()).context("Failed to parse tdtk config"),
+        Err(_) => Ok(Config::default()),
+    }
+}
@@ -1,9 +1,11 @@
 use std::fmt;
+use std::io::{self, IsTerminal};
 use std::process::Command;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Duration;
 use derive_more::Display;
-use inquire::{Select, Text};
+use inquire::{Confirm, Password, PasswordDisplayMode, Select, Text};
 use jsonschema::JSONSchema;
 use serde_yaml::Value;
 
@@ -14,7 +16,7 @@ pub struct Choice<T> where T: fmt::Display {
     pub(crate) prompt: String,
 }
 
-#[derive(Display)]
+#[derive(Display, Clone, Copy)]
 pub enum Action {
     Generate,
     Import,
@@ -26,26 +28,59 @@ pub enum Chores {
     Service,
 }
 
+pub fn ensure_tty(prompt: &str) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        bail!("Cannot ask `{}`, stdin is not a TTY. Pass the value as an argument instead", prompt);
+    }
+    Ok(())
+}
+
 pub fn select<T>(prompt: &str, choices: Vec<T>) -> Result<T> where T: fmt::Display {
+    ensure_tty(prompt)?;
     Select::new(prompt, choices)
         .prompt()
         .context(format!("Failed to select `{}`", prompt))
 }
 
 pub fn text<T>(prompt: T) -> Result<String> where T: Into<String> {
-    Text::new(prompt.into().as_str())
+    let prompt = prompt.into();
+    ensure_tty(&prompt)?;
+    Text::new(prompt.as_str())
         .prompt()
         .context("Failed to get input")
 }
 
+pub fn confirm(prompt: &str) -> Result<bool> {
+    ensure_tty(prompt)?;
+    Confirm::new(prompt)
+        .with_default(true)
+        .prompt()
+        .context(format!("Failed to confirm `{}`", prompt))
+}
+
+pub fn password(prompt: &str) -> Result<String> {
+    ensure_tty(prompt)?;
+    Password::new(prompt)
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .without_confirmation()
+        .prompt()
+        .context(format!("Failed to get `{}`", prompt))
+}
+
 pub fn execute_command(command: &str, args: &[&str], wd: Option<&String>) -> Result<String> {
+    let stdout = capture_command(command, args, wd)?;
+    println!("{}", stdout);
+    Ok(stdout)
+}
+
+/// Like `execute_command`, but does not echo stdout, e.g. for commands printing secrets.
+pub fn capture_command(command: &str, args: &[&str], wd: Option<&String>) -> Result<String> {
     let output = Command::new(command)
         .args(args)
         .current_dir(wd.unwrap_or(&".".to_string()))
         .output()
         .context(format!("Failed to execute process `{}`", command))?;
 
-    println!("{}", String::from_utf8_lossy(&output.stdout));
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(anyhow!("Failed to execute command `{} {}`, stderr:\n{}",
@@ -55,6 +90,33 @@ pub fn execute_command(command: &str, args: &[&str], wd: Option<&String>) -> Res
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
+pub fn new_password(prompt: &str) -> Result<String> {
+    ensure_tty(prompt)?;
+    Password::new(prompt)
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .with_custom_confirmation_message("Confirmation:")
+        .prompt()
+        .context(format!("Failed to get `{}`", prompt))
+}
+
+/// Parses durations like `30m`, `12h`, `7d` or `2w`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let unit_index = duration.find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Missing unit in duration `{duration}`, expected one of m, h, d, w"))?;
+    let (amount, unit) = duration.split_at(unit_index);
+    let amount: i64 = amount.parse()
+        .context(format!("Invalid duration `{duration}`"))?;
+
+    match unit {
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(anyhow!("Unknown unit `{unit}` in duration `{duration}`, expected one of m, h, d, w")),
+    }
+}
+
 pub fn validate_yaml_against_schema(yaml: &str) -> Result<()> {
     let yaml_value: Value = serde_yaml::from_str(yaml).unwrap();
     let json_value = serde_json::to_value(yaml_value).unwrap();
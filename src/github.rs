@@ -60,7 +60,7 @@ pub(crate) async fn get_github_token() -> Result<BasicTokenResponse> {
                 let code_pair = url
                     .query_pairs()
                     .find(|pair| {
-                        let &(ref key, _) = pair;
+                        let (key, _) = pair;
                         key == "code"
                     })
                     .unwrap();
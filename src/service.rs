@@ -3,9 +3,9 @@ use std::fs;
 use std::fs::File;
 use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command, ValueEnum};
 use derive_more::Display;
-use oauth2::basic::BasicTokenResponse;
 use oauth2::TokenResponse;
 use octocrab::models::User;
 use octocrab::Octocrab;
@@ -52,11 +52,24 @@ struct Answer {
     value: String,
 }
 
-macro_rules! select_or_return {
-    ($prompt:expr, $choices:expr, $return_if:expr) => {
+/// What to do with a directory or repo of the service that exists already.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Existing {
+    Delete,
+    Keep,
+}
+
+macro_rules! delete_or_return {
+    ($prompt:expr, $existing:expr) => {
         {
-            let answer = select($prompt, $choices)?;
-            if answer == $return_if {
+            let existing = match $existing {
+                Some(existing) => existing,
+                None => select($prompt, vec![
+                    Choice { choice: Existing::Keep, prompt: "No".to_string() },
+                    Choice { choice: Existing::Delete, prompt: "Yes".to_string() },
+                ])?.choice,
+            };
+            if existing == Existing::Keep {
                 print!("Nothing to do, exiting");
                 return Ok(());
             }
@@ -64,10 +77,75 @@ macro_rules! select_or_return {
     };
 }
 
-pub async fn handle_service(repo_template: &String, spec_questions_path: Option<&String>) -> Result<()> {
-    let service_name = text("What is the name of the service?")?
+/// Values for a new service that were already given on the command line.
+/// Everything left empty is prompted for.
+#[derive(Default)]
+pub struct ServiceArgs {
+    name: Option<String>,
+    description: Option<String>,
+    answers: Vec<Answer>,
+    existing_dir: Option<Existing>,
+    existing_repo: Option<Existing>,
+    /// Used instead of signing in to GitHub in the browser, `$GITHUB_TOKEN` if not given.
+    github_token: Option<String>,
+}
+
+pub fn service_command() -> Command {
+    Command::new("service")
+        .about("Manage services")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(Command::new("create")
+            .about("Create a new service repo from the template repo")
+            .arg(arg!(-n --name <NAME> "Name of the service (e.g. 'foo', 'org/foo')"))
+            .arg(arg!(-d --description <DESCRIPTION> "Description of the service"))
+            .arg(arg!(-a --answer <ANSWER> "Answer to a spec question as `name=value`")
+                .action(ArgAction::Append))
+            .arg(arg!(--"existing-dir" <ACTION> "What to do if the service directory exists, instead of asking")
+                .value_parser(value_parser!(Existing)))
+            .arg(arg!(--"existing-repo" <ACTION> "What to do if the service repo exists, instead of asking")
+                .value_parser(value_parser!(Existing)))
+            .arg(arg!(--"github-token" <TOKEN> "GitHub token to use instead of signing in in the browser, defaults to $GITHUB_TOKEN")))
+}
+
+pub async fn handle_service_command(matches: &ArgMatches) -> Result<()> {
+    let repo_template = matches.get_one::<String>("template_repo")
+        .context("Please provide a template repo name")?;
+    let spec_questions_path = matches.get_one::<String>("spec_questions_path");
+
+    match matches.subcommand() {
+        Some(("create", matches)) => {
+            let answers = matches.get_many::<String>("answer")
+                .unwrap_or_default()
+                .map(|answer| parse_answer(answer))
+                .collect::<Result<Vec<Answer>>>()?;
+
+            handle_service(repo_template, spec_questions_path, ServiceArgs {
+                name: matches.get_one::<String>("name").cloned(),
+                description: matches.get_one::<String>("description").cloned(),
+                answers,
+                existing_dir: matches.get_one::<Existing>("existing-dir").copied(),
+                existing_repo: matches.get_one::<Existing>("existing-repo").copied(),
+                github_token: matches.get_one::<String>("github-token").cloned(),
+            }).await
+        }
+        _ => unreachable!("clap requires a service subcommand"),
+    }
+}
+
+fn parse_answer(answer: &str) -> Result<Answer> {
+    let (name, value) = answer.split_once('=')
+        .ok_or_else(|| anyhow!("Invalid answer `{answer}`, expected `name=value`"))?;
+    Ok(Answer { name: name.trim().to_string(), value: value.to_string() })
+}
+
+pub async fn handle_service(repo_template: &str, spec_questions_path: Option<&String>, args: ServiceArgs) -> Result<()> {
+    let service_name = match args.name {
+        Some(name) => name,
+        None => text("What is the name of the service?")?,
+    }
         .trim()
-        .replace(" ", "-");
+        .replace(' ', "-");
 
     if service_name.is_empty() {
         println!("Service name cannot be empty");
@@ -75,18 +153,24 @@ pub async fn handle_service(repo_template: &String, spec_questions_path: Option<
     }
 
     if Path::new(service_name.as_str()).exists() {
-        select_or_return!("Directory exists, do you want to delete it?", vec!["No", "Yes"], "No");
+        delete_or_return!("Directory exists, do you want to delete it?", args.existing_dir);
 
         fs::remove_dir_all(&service_name).context("Failed to remove service directory")?;
     }
 
-    let service_description = text("What is the description of the service?")?;
+    let service_description = match args.description {
+        Some(description) => description,
+        None => text("What is the description of the service?")?,
+    };
 
-    let answers = custom_questions(spec_questions_path)?;
+    let answers = custom_questions(spec_questions_path, args.answers)?;
 
-    let token_response = get_github_token().await?;
+    let token = match args.github_token.or_else(|| std::env::var("GITHUB_TOKEN").ok().filter(|token| !token.is_empty())) {
+        Some(token) => token,
+        None => get_github_token().await?.access_token().secret().to_string(),
+    };
 
-    let octocrab = make_github_client(token_response)?;
+    let octocrab = make_github_client(token)?;
 
     let user = octocrab.current().user().await.context("Failed to get user")?;
     let (repo_owner, repo_name) = split_repo_name(&service_name, &user)?;
@@ -97,7 +181,7 @@ pub async fn handle_service(repo_template: &String, spec_questions_path: Option<
         .await;
 
     if repo.is_ok() {
-        select_or_return!("Repo exists, do you want to delete it?", vec!["No", "Yes"], "No");
+        delete_or_return!("Repo exists, do you want to delete it?", args.existing_repo);
 
         delete_repo(&octocrab, &repo_owner, &repo_name).await?;
     }
@@ -116,9 +200,9 @@ pub async fn handle_service(repo_template: &String, spec_questions_path: Option<
     Ok(())
 }
 
-fn make_github_client(token_response: BasicTokenResponse) -> Result<Octocrab> {
+fn make_github_client(token: String) -> Result<Octocrab> {
     Octocrab::builder()
-        .personal_token(token_response.access_token().secret().to_string())
+        .personal_token(token)
         .build()
         .context("Failed to build octocrab")
 }
@@ -126,15 +210,15 @@ fn make_github_client(token_response: BasicTokenResponse) -> Result<Octocrab> {
 async fn create_repo(
     service_description: String,
     octocrab: Octocrab,
-    repo_owner: &String,
-    repo_name: &String,
+    repo_owner: &str,
+    repo_name: &str,
     template_owner: String,
     template_name: String,
 ) -> Result<()> {
     octocrab
         .repos(template_owner, template_name)
-        .generate(repo_name.as_str())
-        .owner(repo_owner.as_str())
+        .generate(repo_name)
+        .owner(repo_owner)
         .description(service_description.as_str())
         .private(true)
         .send()
@@ -144,10 +228,10 @@ async fn create_repo(
 
 async fn delete_repo(
     octocrab: &Octocrab,
-    repo_owner: &String,
-    repo_name: &String,
+    repo_owner: &str,
+    repo_name: &str,
 ) -> Result<()> {
-    octocrab.repos(repo_owner.as_str(), repo_name.as_str())
+    octocrab.repos(repo_owner, repo_name)
         .delete()
         .await
         .context("Failed to delete repo")
@@ -177,7 +261,7 @@ fn add_service_specs(
 }
 
 fn create_spec_file(spec_path: &String) -> Result<BTreeMap<String, String>> {
-    File::create(&spec_path)
+    File::create(spec_path)
         .context(format!("Failed to create {spec_path}"))?;
 
     let specs_file = fs::read_to_string(spec_path.as_str())
@@ -211,7 +295,7 @@ macro_rules! check_condition {
     };
 }
 
-fn custom_questions(spec_questions_path: Option<&String>) -> Result<Vec<Answer>> {
+fn custom_questions(spec_questions_path: Option<&String>, given_answers: Vec<Answer>) -> Result<Vec<Answer>> {
     let mut answers: Vec<Answer> = Vec::new();
 
     if let Some(spec_questions_path) = spec_questions_path {
@@ -227,6 +311,10 @@ fn custom_questions(spec_questions_path: Option<&String>) -> Result<Vec<Answer>>
             if let Some(condition) = &question.condition {
                 check_condition!(condition, answers);
             }
+            if let Some(answer) = given_answers.iter().find(|a| a.name == question.name) {
+                answers.push(answer.clone());
+                continue;
+            }
             if let Some(options) = &question.options {
                 answers.push(get_answer(&question.name, &question.question, options)?);
             } else {
@@ -242,31 +330,31 @@ fn custom_questions(spec_questions_path: Option<&String>) -> Result<Vec<Answer>>
     Ok(answers)
 }
 
-fn get_answer(name: &String, question: &String, options: &Vec<OptionElement>) -> Result<Answer> {
+fn get_answer(name: &str, question: &str, options: &[OptionElement]) -> Result<Answer> {
     let options: Vec<Choice<String>> = options.iter()
         .map(|o| Choice { prompt: o.display.clone(), choice: o.value.clone() })
         .collect();
 
     let ans = select(question, options)?;
     let answer = Answer {
-        name: name.clone(),
+        name: name.to_string(),
         value: ans.choice,
     };
 
     Ok(answer)
 }
 
-fn split_repo_name(service_name: &String, user: &User) -> Result<(String, String)> {
+fn split_repo_name(service_name: &str, user: &User) -> Result<(String, String)> {
     let repo_owner;
     let repo_name;
 
-    if service_name.contains("/") {
-        let mut parts = service_name.split("/");
+    if service_name.contains('/') {
+        let mut parts = service_name.split('/');
         repo_owner = parts.next().unwrap().to_string();
         repo_name = parts.next().unwrap().to_string();
     } else {
         repo_owner = user.clone().login;
-        repo_name = service_name.clone();
+        repo_name = service_name.to_string();
     }
 
     Ok((repo_owner, repo_name))
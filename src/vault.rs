@@ -1,158 +1,689 @@
 use std::{fs, io, str};
-use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-
-use ansible_vault::decrypt_vault_from_file;
-use anyhow::{Context, Result};
-use base64::Engine;
-use inquire::{Password, PasswordDisplayMode, Text};
-use rand_chacha::ChaCha20Rng;
-use rand_chacha::rand_core::{RngCore, SeedableRng};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{arg, ArgAction, ArgMatches, Command, value_parser};
+use inquire::Text;
+use chrono::{Duration, Utc};
 use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use walkdir::WalkDir;
 
 use crate::completer::FilePathCompleter;
-use crate::core::{Choice, execute_command, select, text};
+use crate::config::Config;
+use crate::core::{Action, Choice, confirm, ensure_tty, parse_duration, password, select, text};
 use crate::core::Action::{Generate, Import};
+use crate::vault::inventory::{Inventory, prompt_target, Target, target_vault_file};
+use crate::vault::inline::{add_inline_secret_to_file, is_plain_yaml, read_inline_secret};
+use crate::vault::audit::{audit_command, handle_audit_command};
+use crate::vault::agent::{agent_command, handle_agent_command};
+use crate::vault::document::VaultDocument;
+use crate::vault::import::{handle_import_command, import_command};
+use crate::vault::key_path::{KeyPath, scalar_to_string};
+use crate::vault::keygen::{Generator, KeyType, prompt_generator, write_public_half};
+use crate::vault::metadata::{expiring_command, handle_expiring_command, SecretMetadata, update_metadata};
+use crate::vault::policy::resolve_policy;
+pub use crate::vault::policy::SecretPolicy;
+use crate::vault::edit::{edit_command, handle_edit_command};
+use crate::vault::exec::{exec_command, handle_exec_command};
+use crate::vault::export::{export_command, handle_export_command};
+use crate::vault::files::{file_commands, handle_decrypt_file_command, handle_encrypt_file_command, read_secret_file};
+use crate::vault::git::{git_commands, handle_git_setup_command, handle_merge_command, handle_textconv_command};
+use crate::vault::rekey::{handle_rekey_command, rekey_command};
+use crate::vault::usage::{handle_usage_command, usage_command};
+use crate::vault::storage::{backup_vault_file, handle_restore_command, lock_vault_file, restore_command, write_atomically};
+use crate::vault::vars::add_vars_indirection;
+use crate::vault::vault_id::{vault_id_args, VaultIds, VaultPasswords};
 
-pub fn handle_vault_secret() -> Result<()> {
-    let vault_password = if std::env::var("ANSIBLE_VAULT_PASSWORD_FILE").is_err() {
-        Password::new("Ansible vault password:")
-            .with_display_mode(PasswordDisplayMode::Masked)
-            .without_confirmation()
-            .prompt()
-            .context("Failed to get vault password")?
-    } else {
-        println!("Using ANSIBLE_VAULT_PASSWORD_FILE environment variable");
-        get_vault_password()?
+mod inline;
+mod agent;
+mod ansible_cfg;
+mod audit;
+mod document;
+mod edit;
+mod exec;
+mod export;
+mod files;
+mod git;
+mod import;
+mod inventory;
+mod key_path;
+mod keygen;
+mod metadata;
+mod policy;
+mod rekey;
+mod storage;
+mod usage;
+mod vars;
+mod vault_id;
+mod yaml_edit;
+
+const VAULT_HEADER: &str = "$ANSIBLE_VAULT;";
+const PREVIOUS_SUFFIX: &str = "_previous";
+const EXPIRES_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Values for a vault secret that were already given on the command line.
+/// Everything left as `None` is prompted for.
+#[derive(Default)]
+pub struct SecretArgs {
+    pub(crate) vault_file: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) action: Option<Action>,
+    pub(crate) secret: Option<String>,
+    pub(crate) policy: Option<String>,
+    pub(crate) key_type: Option<KeyType>,
+    pub(crate) subject_alt_names: Vec<String>,
+    pub(crate) print_public: bool,
+    pub(crate) vault_ids: VaultIds,
+    pub(crate) inline: bool,
+    pub(crate) vars: bool,
+    pub(crate) expires_in: Option<Duration>,
+}
+
+pub fn vault_command() -> Command {
+    Command::new("vault")
+        .about("Manage ansible vault secrets")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .args(vault_id_args())
+        .subcommand(Command::new("add")
+            .about("Add a secret to a vault file, the vault file is created if missing")
+            .arg(arg!(-f --file <FILE> "Path to the vault file"))
+            .arg(arg!(--group <GROUP> "Add to the vault file of an inventory group, `group_vars/<GROUP>/vault.yml`")
+                .conflicts_with_all(["file", "inline"]))
+            .arg(arg!(--host <HOST> "Add to the vault file of an inventory host, `host_vars/<HOST>/vault.yml`")
+                .conflicts_with_all(["file", "inline", "group"]))
+            .arg(arg!(-n --name <NAME> "Name of the secret, prefixed with `vault_` if missing"))
+            .arg(arg!(-g --generate "Generate a new random secret")
+                .conflicts_with("value"))
+            .arg(arg!(-p --policy <POLICY> "Secret policy used to generate the secret (e.g. hex32, passphrase)")
+                .conflicts_with("value"))
+            .arg(arg!(-k --key <TYPE> "Generate key material instead of a random secret")
+                .value_parser(value_parser!(KeyType))
+                .conflicts_with_all(["value", "policy"]))
+            .arg(arg!(--san <HOST> "Host name covered by a generated TLS certificate")
+                .action(ArgAction::Append)
+                .requires("key"))
+            .arg(arg!(--"print-public" "Print the public half of generated key material instead of writing it next to the vault file")
+                .requires("key"))
+            .arg(arg!(--value <VALUE> "The secret to import, `-` reads it from stdin"))
+            .arg(arg!(--"from-file" <PATH> "Import the content of a file, e.g. a certificate, stored as a multi-line value")
+                .conflicts_with_all(["value", "generate", "policy", "key"]))
+            .arg(arg!(--base64 "Store the file base64 encoded, for binary files like keystores")
+                .requires("from-file"))
+            .arg(arg!(--inline "Store the secret as an inline `!vault` value in a plain YAML vars file, without the `vault_` prefix"))
+            .arg(arg!(--"expires-in" <DURATION> "When the secret expires, recorded in the secret metadata (e.g. 90d)")
+                .value_parser(parse_duration)
+                .conflicts_with("inline"))
+            .arg(arg!(--vars "Also add `foo: \"{{ vault_foo }}\"` to the plain vars file next to the vault file")
+                .conflicts_with("inline")))
+        .subcommand(Command::new("list")
+            .about("List the secret names of a vault file")
+            .arg(arg!(<FILE> "Path to the vault file"))
+            .arg(arg!(-l --length "Also print the length of each secret"))
+            .arg(arg!(--fingerprint "Also print a short fingerprint of each secret, keyed by the vault password")))
+        .subcommand(Command::new("show")
+            .about("Print the value of a single secret, from a vault file or an inline `!vault` value")
+            .arg(arg!(<FILE> "Path to the vault file or plain YAML vars file"))
+            .arg(arg!(<KEY> "Name of the secret, the `vault_` prefix is optional")))
+        .subcommand(Command::new("rm")
+            .about("Remove a secret from a vault file")
+            .arg(arg!(<FILE> "Path to the vault file"))
+            .arg(arg!(<KEY> "Name of the secret, the `vault_` prefix is optional")))
+        .subcommand(Command::new("mv")
+            .about("Rename a secret in a vault file")
+            .arg(arg!(<FILE> "Path to the vault file"))
+            .arg(arg!(<OLD> "Current name of the secret, the `vault_` prefix is optional"))
+            .arg(arg!(<NEW> "New name of the secret, prefixed with `vault_` if missing")))
+        .subcommand(Command::new("rotate")
+            .about("Replace a secret and keep the old value as `<KEY>_previous` for a grace period")
+            .arg(arg!(<FILE> "Path to the vault file"))
+            .arg(arg!([KEY] "Name of the secret, the `vault_` prefix is optional")
+                .required_unless_present("finalize"))
+            .arg(arg!(--value <VALUE> "The new secret to import instead of generating one, `-` reads it from stdin"))
+            .arg(arg!(-p --policy <POLICY> "Secret policy used to generate the new secret (e.g. hex32, passphrase)")
+                .conflicts_with("value"))
+            .arg(arg!(--grace <DURATION> "How long the previous value is kept (e.g. 12h, 7d, 2w)")
+                .value_parser(parse_duration)
+                .default_value("7d"))
+            .arg(arg!(--"expires-in" <DURATION> "When the new value expires, recorded in the secret metadata (e.g. 90d)")
+                .value_parser(parse_duration))
+            .arg(arg!(--finalize "Drop the previous value of KEY, or all expired previous values without KEY")
+                .conflicts_with_all(["value", "grace", "policy", "expires-in"])))
+        .subcommand(import_command())
+        .subcommand(export_command())
+        .subcommand(exec_command())
+        .subcommands(file_commands())
+        .subcommand(edit_command())
+        .subcommand(rekey_command())
+        .subcommand(restore_command())
+        .subcommands(git_commands())
+        .subcommand(agent_command())
+        .subcommand(audit_command())
+        .subcommand(usage_command())
+        .subcommand(expiring_command())
+}
+
+pub fn handle_vault_command(matches: &ArgMatches, config: &Config) -> Result<()> {
+    let vault_ids = VaultIds::from_matches(matches);
+
+    match matches.subcommand() {
+        Some(("add", matches)) => {
+            let secret = match (matches.get_one::<String>("value"), matches.get_one::<String>("from-file")) {
+                (_, Some(path)) => Some(read_secret_file(path, matches.get_flag("base64"))?),
+                (Some(value), _) if value == "-" => Some(read_secret_from_stdin()?),
+                (value, _) => value.cloned(),
+            };
+            let policy = matches.get_one::<String>("policy").cloned();
+            let key_type = matches.get_one::<KeyType>("key").copied();
+            let action = if matches.get_flag("generate") || policy.is_some() || key_type.is_some() {
+                Some(Generate)
+            } else {
+                secret.as_ref().map(|_| Import)
+            };
+
+            let target = match (matches.get_one::<String>("group"), matches.get_one::<String>("host")) {
+                (Some(group), _) => Some(Target::Group(group.clone())),
+                (_, Some(host)) => Some(Target::Host(host.clone())),
+                _ => None,
+            };
+            let vault_file = match target {
+                Some(target) => Some(target_vault_file(&target, config)?),
+                None => matches.get_one::<String>("file").cloned(),
+            };
+
+            handle_vault_secret(SecretArgs {
+                vault_file,
+                name: matches.get_one::<String>("name").cloned(),
+                action,
+                secret,
+                policy,
+                key_type,
+                subject_alt_names: matches.get_many::<String>("san").unwrap_or_default().cloned().collect(),
+                print_public: matches.get_flag("print-public"),
+                vault_ids,
+                inline: matches.get_flag("inline"),
+                vars: matches.get_flag("vars"),
+                expires_in: matches.get_one::<Duration>("expires-in").copied(),
+            }, config)
+        }
+        Some(("list", matches)) => handle_vault_list(
+            matches.get_one::<String>("FILE").unwrap(),
+            matches.get_flag("length"),
+            matches.get_flag("fingerprint"),
+            &vault_ids,
+        ),
+        Some(("show", matches)) => handle_vault_show(
+            matches.get_one::<String>("FILE").unwrap(),
+            matches.get_one::<String>("KEY").unwrap(),
+            &vault_ids,
+        ),
+        Some(("rm", matches)) => handle_vault_rm(
+            matches.get_one::<String>("FILE").unwrap(),
+            matches.get_one::<String>("KEY").unwrap(),
+            &vault_ids,
+            config,
+        ),
+        Some(("mv", matches)) => handle_vault_mv(
+            matches.get_one::<String>("FILE").unwrap(),
+            matches.get_one::<String>("OLD").unwrap(),
+            matches.get_one::<String>("NEW").unwrap(),
+            &vault_ids,
+            config,
+        ),
+        Some(("rotate", matches)) => {
+            let vault_file_path = matches.get_one::<String>("FILE").unwrap();
+            let key = matches.get_one::<String>("KEY");
+            if matches.get_flag("finalize") {
+                return handle_vault_rotate_finalize(vault_file_path, key, &vault_ids, config);
+            }
+
+            let secret = match matches.get_one::<String>("value") {
+                Some(value) if value == "-" => Some(read_secret_from_stdin()?),
+                value => value.cloned(),
+            };
+            handle_vault_rotate(vault_file_path, key.unwrap(), RotateArgs {
+                secret,
+                policy: matches.get_one::<String>("policy"),
+                grace: *matches.get_one::<Duration>("grace").unwrap(),
+                expires_in: matches.get_one::<Duration>("expires-in").copied(),
+            }, &vault_ids, config)
+        }
+        Some(("import", matches)) => handle_import_command(matches, &vault_ids, config),
+        Some(("export", matches)) => handle_export_command(matches, &vault_ids),
+        Some(("exec", matches)) => handle_exec_command(matches, &vault_ids),
+        Some(("encrypt-file", matches)) => handle_encrypt_file_command(matches, &vault_ids),
+        Some(("decrypt-file", matches)) => handle_decrypt_file_command(matches, &vault_ids),
+        Some(("edit", matches)) => handle_edit_command(matches, &vault_ids, config),
+        Some(("rekey", matches)) => handle_rekey_command(matches, &vault_ids),
+        Some(("restore", matches)) => handle_restore_command(matches, config),
+        Some(("textconv", matches)) => handle_textconv_command(matches, &vault_ids),
+        Some(("merge", matches)) => handle_merge_command(matches, &vault_ids),
+        Some(("git-setup", matches)) => handle_git_setup_command(matches, &vault_ids),
+        Some(("agent", matches)) => handle_agent_command(matches),
+        Some(("audit", matches)) => handle_audit_command(matches, &vault_ids),
+        Some(("usage", matches)) => handle_usage_command(matches, &vault_ids),
+        Some(("expiring", matches)) => handle_expiring_command(matches),
+        _ => unreachable!("clap requires a vault subcommand"),
+    }
+}
+
+fn handle_vault_list(vault_file_path: &str, length: bool, fingerprint: bool, vault_ids: &VaultIds) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    let (vault_file, label) = decrypt_vault_file_with_label(vault_file_path, &vault)?;
+
+    for (path, value) in KeyPath::leaves(vault_file.values()) {
+        let secret = scalar_to_string(value);
+        let mut line = path.to_string();
+        if length {
+            line.push_str(&format!("\t{}", secret.chars().count()));
+        }
+        if fingerprint {
+            line.push_str(&format!("\t{}", vault.fingerprint(label.as_deref(), &secret)?));
+        }
+        println!("{line}");
+    }
+
+    Ok(())
+}
+
+fn handle_vault_show(vault_file_path: &str, key: &str, vault_ids: &VaultIds) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    if is_plain_yaml(vault_file_path) {
+        let secret = read_inline_secret(key, vault_file_path, &vault)?
+            .with_context(|| format!("`{key}` not found in {vault_file_path}"))?;
+        println!("{secret}");
+        return Ok(());
+    }
+    let vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+
+    let path = find_secret_path(vault_file.values(), key, vault_file_path)?;
+    println!("{}", scalar_to_string(vault_file.get(&path).unwrap()));
+
+    Ok(())
+}
+
+fn handle_vault_rm(vault_file_path: &str, key: &str, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let mut vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+
+    let path = find_secret_path(vault_file.values(), key, vault_file_path)?;
+    vault_file.remove(&path)?;
+    write_vault_file(vault_file_path, &vault_file, &vault, config.vault_backups)?;
+
+    println!("Removed {path} from {vault_file_path}");
+    update_metadata(vault_file_path, config, false, |metadata| metadata.removed(&path.to_string()))
+}
+
+fn handle_vault_mv(vault_file_path: &str, old_key: &str, new_key: &str, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let mut vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+
+    let old_path = find_secret_path(vault_file.values(), old_key, vault_file_path)?;
+    let new_path = KeyPath::normalized(new_key);
+    if old_path == new_path {
+        bail!("`{old_path}` and `{new_path}` are the same secret");
+    }
+    if vault_file.get(&new_path).is_some() {
+        bail!("`{new_path}` already exists in {vault_file_path}");
+    }
+
+    vault_file.rename(&old_path, &new_path)?;
+    write_vault_file(vault_file_path, &vault_file, &vault, config.vault_backups)?;
+
+    println!("Renamed {old_path} to {new_path} in {vault_file_path}");
+    update_metadata(vault_file_path, config, false, |metadata| metadata.renamed(&old_path.to_string(), &new_path.to_string()))
+}
+
+/// How `tdtk vault rotate` replaces a secret.
+struct RotateArgs<'a> {
+    secret: Option<String>,
+    policy: Option<&'a String>,
+    grace: Duration,
+    expires_in: Option<Duration>,
+}
+
+fn handle_vault_rotate(vault_file_path: &str, key: &str, args: RotateArgs, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let mut vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+
+    let path = find_secret_path(vault_file.values(), key, vault_file_path)?;
+    let previous_path = path.with_suffix(PREVIOUS_SUFFIX);
+    if vault_file.get(&previous_path).is_some() {
+        bail!("`{previous_path}` still exists, run `tdtk vault rotate {vault_file_path} {path} --finalize` first");
+    }
+
+    let secret = match args.secret {
+        Some(secret) => secret,
+        None => generate_secret(&path.to_string(), args.policy, false, config)?,
     };
+    let previous = vault_file.set(&path, Value::String(secret))?.unwrap();
+    let expires = Utc::now() + args.grace;
+    vault_file.set(&previous_path, previous)?;
+    write_vault_file(vault_file_path, &vault_file, &vault, config.vault_backups)?;
 
-    let min_length = 4;
-    if vault_password.len() < min_length {
-        println!("The vault password must be at least {min_length} characters long");
-        handle_vault_secret()?
-    } else {
-        match select("Do you want to generate a new secret?", vec![
-            Choice { choice: Generate, prompt: "Generate a new secret".to_string() },
-            Choice { choice: Import, prompt: "Import a secret".to_string() },
-        ]) {
-            Ok(choice) => {
-                match choice.choice {
-                    Generate => handle_vault_secret_generate(&vault_password)?,
-                    Import => handle_vault_secret_import(&vault_password)?,
-                }
+    println!("Rotated {path}, the old value is kept as {previous_path} until {}", expires.format(EXPIRES_FORMAT));
+    // The expiry of the old value is always recorded, `--finalize` without a key drops it by this
+    update_metadata(vault_file_path, config, true, |metadata| metadata.rotated(&path.to_string(), args.expires_in, expires))
+}
+
+fn handle_vault_rotate_finalize(vault_file_path: &str, key: Option<&String>, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let mut vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+    let metadata = SecretMetadata::open(vault_file_path, config, false)?;
+
+    let paths: Vec<KeyPath> = match key {
+        Some(key) => {
+            let path = find_secret_path(vault_file.values(), key, vault_file_path)?;
+            if vault_file.get(&path.with_suffix(PREVIOUS_SUFFIX)).is_none() {
+                bail!("`{path}` has no previous value in {vault_file_path}");
+            }
+            vec![path]
+        }
+        None => metadata.as_ref()
+            .map(|metadata| metadata.expired_previous(Utc::now()))
+            .unwrap_or_default()
+            .iter()
+            .map(|name| KeyPath::parse(name))
+            .filter(|path| vault_file.get(&path.with_suffix(PREVIOUS_SUFFIX)).is_some())
+            .collect(),
+    };
+
+    if paths.is_empty() {
+        println!("No expired previous values in {vault_file_path}");
+        return Ok(());
+    }
+
+    for path in &paths {
+        let previous_path = path.with_suffix(PREVIOUS_SUFFIX);
+        vault_file.remove(&previous_path)?;
+        println!("Dropped {previous_path}");
+    }
+    write_vault_file(vault_file_path, &vault_file, &vault, config.vault_backups)?;
+
+    match metadata {
+        Some(mut metadata) => {
+            for path in &paths {
+                metadata.previous_dropped(&path.to_string())?;
             }
-            Err(_) => println!("There was an error, please try again"),
-        };
+            metadata.save()
+        }
+        None => Ok(()),
     }
+}
+
+/// Looks up the dotted `key` as given, then with the `vault_` normalization applied.
+fn find_secret_path(vault_file: &Mapping, key: &str, vault_file_path: &str) -> Result<KeyPath> {
+    [KeyPath::parse(key), KeyPath::normalized(key)].into_iter()
+        .find(|path| path.get(vault_file).is_some())
+        .with_context(|| format!("`{key}` not found in {vault_file_path}"))
+}
+
+pub fn handle_vault_secret(args: SecretArgs, config: &Config) -> Result<()> {
+    let vault = args.vault_ids.passwords()?;
 
+    let interactive = args.action.is_none();
+    let action = match args.action {
+        Some(action) => action,
+        None => select("Do you want to generate a new secret?", vec![
+            Choice { choice: Generate, prompt: "Generate a new secret".to_string() },
+            Choice { choice: Import, prompt: "Import a secret".to_string() },
+        ])?.choice,
+    };
+
+    let (secret_name, vault_file_path) = match action {
+        Generate => handle_vault_secret_generate(&vault, &args, interactive, config)?,
+        Import => handle_vault_secret_import(&vault, &args, config)?,
+    };
+
+    if !args.inline {
+        update_metadata(&vault_file_path, config, args.expires_in.is_some(), |metadata| metadata.updated(&secret_name, args.expires_in))?;
+    }
+    if !args.inline && (args.vars || interactive && confirm("Also add it to the plain vars file next to the vault file?")?) {
+        add_vars_indirection(&secret_name, &vault_file_path)?;
+    }
     Ok(())
 }
 
-fn prompt_secret_name() -> Result<String> {
-    let name = text("What is the name of the secret?");
+fn read_secret_from_stdin() -> Result<String> {
+    let mut secret = String::new();
+    io::stdin().read_to_string(&mut secret).context("Failed to read secret from stdin")?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
 
-    name.map(|name| {
-        let re = Regex::new(r"[^A-Za-z0-9]").unwrap();
-        let name = re.replace_all(&name.trim().to_string(), "_").to_string();
-        if name.starts_with("vault_") { name } else { format!("vault_{}", name) }
-    }).context("Failed to get secret name")
+fn normalize_variable_name(name: &str) -> String {
+    let re = Regex::new(r"[^A-Za-z0-9]").unwrap();
+    re.replace_all(name.trim(), "_").to_string()
 }
 
-fn handle_vault_secret_import(vault_password: &String) -> Result<()> {
-    let secret_name = prompt_secret_name()?;
-    let secret = Password::new("The secret text")
-        .with_display_mode(PasswordDisplayMode::Masked)
-        .without_confirmation()
-        .prompt()
-        .context("Failed to get secret text")?;
+fn normalize_secret_name(name: &str) -> String {
+    let name = normalize_variable_name(name);
+    if name.starts_with("vault_") { name } else { format!("vault_{}", name) }
+}
 
-    add_vault_secret(vault_password, &secret_name, Some(&secret))
+/// Inline `!vault` values are the variables themselves, so they don't get the `vault_` prefix.
+/// Secrets in vault files can be nested with dotted names, e.g. `db.primary.password`.
+fn prompt_secret_name(name: Option<&String>, inline: bool) -> Result<String> {
+    let normalize = |name: &str| if inline {
+        normalize_variable_name(name)
+    } else {
+        KeyPath::normalized(name).to_string()
+    };
+    match name {
+        Some(name) => Ok(normalize(name)),
+        None => text("What is the name of the secret?")
+            .map(|name| normalize(&name))
+            .context("Failed to get secret name"),
+    }
 }
 
-fn handle_vault_secret_generate(vault_password: &String) -> Result<()> {
-    let secret_name = prompt_secret_name()?;
-    add_vault_secret(vault_password, &secret_name, None)
+/// Returns the secret name and the vault file path.
+fn handle_vault_secret_import(vault: &VaultPasswords, args: &SecretArgs, config: &Config) -> Result<(String, String)> {
+    let secret_name = prompt_secret_name(args.name.as_ref(), args.inline)?;
+    let secret = match &args.secret {
+        Some(secret) => secret.clone(),
+        None => password("The secret text")?,
+    };
+
+    let vault_file_path = add_vault_secret(vault, &secret_name, &secret, args.vault_file.as_ref(), args.inline, config)?;
+    Ok((secret_name, vault_file_path))
 }
 
-fn add_vault_secret(vault_password: &String, secret_name: &String, secret: Option<&String>) -> Result<()> {
-    let vault_file_path = prompt_vault_file_path()?;
+/// Returns the secret name and the vault file path.
+fn handle_vault_secret_generate(vault: &VaultPasswords, args: &SecretArgs, interactive: bool, config: &Config) -> Result<(String, String)> {
+    let generator = match args.key_type {
+        Some(key_type) => Generator::Key(key_type),
+        None if interactive => prompt_generator()?,
+        None => Generator::Random,
+    };
+    let secret_name = prompt_secret_name(args.name.as_ref(), args.inline)?;
+
+    let vault_file_path = match generator {
+        Generator::Random => {
+            let secret = generate_secret(&secret_name, args.policy.as_ref(), interactive, config)?;
+            add_vault_secret(vault, &secret_name, &secret, args.vault_file.as_ref(), args.inline, config)?
+        }
+        Generator::Key(key_type) => {
+            let key = key_type.generate(&secret_name, &args.subject_alt_names)?;
+            let vault_file_path = add_vault_secret(vault, &secret_name, &key.private, args.vault_file.as_ref(), args.inline, config)?;
+
+            if let Some(public) = key.public {
+                if args.print_public {
+                    print!("{}", public.content);
+                } else {
+                    let public_path = write_public_half(&public, &secret_name, &vault_file_path)?;
+                    println!("Wrote the public half of {secret_name} to {}", public_path.display());
+                }
+            }
+            vault_file_path
+        }
+    };
+
+    Ok((secret_name, vault_file_path))
+}
+
+/// Adds the secret to the vault file, creating it if missing, and returns the absolute vault file path.
+/// With `inline` the secret is added as an inline `!vault` value to a plain YAML file instead.
+fn add_vault_secret(
+    vault: &VaultPasswords,
+    secret_name: &str,
+    secret: &str,
+    vault_file: Option<&String>,
+    inline: bool,
+    config: &Config,
+) -> Result<String> {
+    let vault_file_path = prompt_vault_file_path(vault_file, inline, config)?;
     let path = Path::new(vault_file_path.as_str());
+    let _lock = if path.is_dir() { None } else { Some(lock_vault_file(&vault_file_path)?) };
+
+    if inline && !path.is_dir() {
+        add_inline_secret_to_file(secret_name, secret, &vault_file_path, vault)?;
+        return Ok(vault_file_path);
+    }
 
     if !path.exists() {
         io::stdout().flush()?;
-        create_vault_file(vault_file_path.as_str(), vault_password)?;
+        create_vault_file(vault_file_path.as_str(), vault)?;
     }
 
     if !path.is_file() {
+        if vault_file.is_some() {
+            bail!("`{}` is not a file", vault_file_path);
+        }
         add_vault_secret(
-            vault_password,
+            vault,
             secret_name,
             secret,
-        )?;
+            None,
+            inline,
+            config,
+        )
     } else {
         let absolute_vault_file_path = fs::canonicalize(vault_file_path)
             .context("Failed to get absolute vault file path")?;
         let absolute_vault_file_path = absolute_vault_file_path.to_str().unwrap();
 
-        let generated_secret = generate_secret();
-        let secret = secret.unwrap_or(&generated_secret);
-
-        add_vault_secret_to_file(&secret_name, &secret, absolute_vault_file_path, &vault_password)?
+        add_vault_secret_to_file(secret_name, secret, absolute_vault_file_path, vault, config.vault_backups)?;
+        Ok(absolute_vault_file_path.to_string())
     }
-
-    Ok(())
 }
 
-fn generate_secret() -> String {
-    let mut rng = ChaCha20Rng::from_entropy();
-    let mut bytes = [0u8; 32];
-    rng.fill_bytes(&mut bytes);
-    let secret = base64::engine::general_purpose::STANDARD.encode(&bytes);
-    secret
+fn generate_secret(secret_name: &str, policy: Option<&String>, prompt_policy: bool, config: &Config) -> Result<String> {
+    resolve_policy(secret_name, policy, prompt_policy, config)?.generate()
 }
 
-fn prompt_vault_file_path() -> Result<String> {
-    let vault_file_path = Text::new("Where is the vault file located? (tab to autocomplete)")
-        .with_autocomplete(FilePathCompleter::default())
-        .prompt()
-        .context("Failed to get vault file path")?;
-    if vault_file_path.starts_with("./") || vault_file_path.starts_with("/") {
+/// Without a path, offers the groups and hosts of the inventory before asking for a path.
+fn prompt_vault_file_path(vault_file: Option<&String>, inline: bool, config: &Config) -> Result<String> {
+    let inventory = if vault_file.is_none() && !inline { Inventory::load(config)? } else { None };
+    let target = match &inventory {
+        Some(inventory) => prompt_target(inventory)?,
+        None => None,
+    };
+
+    let vault_file_path = match (vault_file, target) {
+        (Some(vault_file), _) => vault_file.clone(),
+        (None, Some(target)) => target.vault_file_path(&inventory.unwrap().dir)?.to_string_lossy().to_string(),
+        (None, None) => {
+            let prompt = "Where is the vault file located? (tab to autocomplete)";
+            ensure_tty(prompt)?;
+            Text::new(prompt)
+                .with_autocomplete(FilePathCompleter::default())
+                .prompt()
+                .context("Failed to get vault file path")?
+        }
+    };
+    if vault_file_path.starts_with("./") || vault_file_path.starts_with('/') {
         Ok(vault_file_path)
     } else {
         Ok("./".to_string() + &*vault_file_path)
     }
 }
 
-fn add_vault_secret_to_file(secret_name: &String, secret: &String, vault_file_path: &str, password: &String) -> Result<()> {
-    let mut vault_file = decrypt_vault_file(vault_file_path, password)?;
-    vault_file.insert(secret_name.clone(), secret.clone());
+fn add_vault_secret_to_file(secret_name: &str, secret: &str, vault_file_path: &str, vault: &VaultPasswords, backups: usize) -> Result<()> {
+    let mut vault_file = decrypt_vault_file(vault_file_path, vault)?;
+    vault_file.set(&KeyPath::parse(secret_name), Value::String(secret.to_string()))?;
 
-    let vault_file_string = serde_yaml::to_string(&vault_file)?;
-    let encrypted = ansible_vault::encrypt_vault(vault_file_string.as_bytes(), password.as_str())
-        .context("Failed to encrypt vault")?;
+    write_vault_file(vault_file_path, &vault_file, vault, backups)
+}
+
+/// Callers hold the lock of the vault file from reading it until here.
+fn write_vault_file(vault_file_path: &str, vault_file: &VaultDocument, vault: &VaultPasswords, backups: usize) -> Result<()> {
+    let encrypted = vault.encrypt_for_file(vault_file_path, vault_file.content().as_bytes())?;
 
-    fs::write(vault_file_path, encrypted).context("Failed to write vault file")
+    backup_vault_file(Path::new(vault_file_path), backups)?;
+    write_atomically(Path::new(vault_file_path), encrypted.as_bytes())
 }
 
-fn decrypt_vault_file(file: &str, password: &String) -> Result<BTreeMap<String, String>> {
-    let decrypted = decrypt_vault_from_file(file, password.as_str())
+fn decrypt_vault_file(file: &str, vault: &VaultPasswords) -> Result<VaultDocument> {
+    Ok(decrypt_vault_file_with_label(file, vault)?.0)
+}
+
+/// Also returns the label of the vault ID that decrypted the file.
+fn decrypt_vault_file_with_label(file: &str, vault: &VaultPasswords) -> Result<(VaultDocument, Option<String>)> {
+    let encrypted = fs::read(file).context(format!("Failed to read {file}"))?;
+    let (decrypted, label) = vault.decrypt_with_label(&encrypted)
+        .context("Failed to decrypt vault file")?;
+    let content = String::from_utf8(decrypted).context("UTF-8 content expected")?;
+    let document = VaultDocument::parse(content).context(format!("Failed to read {file}"))?;
+    Ok((document, label))
+}
+
+/// Like `decrypt_vault_file`, but `None` for vault files that don't hold YAML vars, e.g. whole-file vaults.
+fn decrypt_vars_file(file: &str, vault: &VaultPasswords) -> Result<Option<VaultDocument>> {
+    let decrypted = vault.decrypt_file(file)
         .context("Failed to decrypt vault file")?;
-    serde_yaml::from_str(str::from_utf8(&decrypted).context("UTF-8 content expected")?)
-        .context("Failed to parse decrypted vault file")
+    Ok(String::from_utf8(decrypted).ok().and_then(|content| VaultDocument::parse(content).ok()))
 }
 
-fn create_vault_file(file_path: &str, password: &String) -> Result<usize> {
-    let mut file = File::create(file_path).context("Failed to create vault file")?;
-    let vault = ansible_vault::encrypt_vault("---".as_bytes(), password.as_str())
-        .context("Failed to encrypt vault")?;
-    file.write(vault.as_bytes()).context("Failed to write vault file")
+fn create_vault_file(file_path: &str, vault: &VaultPasswords) -> Result<()> {
+    let encrypted = vault.encrypt("---".as_bytes())?;
+    write_atomically(Path::new(file_path), encrypted.as_bytes())
+}
+
+/// Resolves a file, glob or directory to the ansible vault files it contains.
+/// Directories are searched recursively, skipping hidden directories like `.git`.
+fn find_vault_files(path: &str) -> Result<Vec<PathBuf>> {
+    let paths: Vec<PathBuf> = if path.contains(['*', '?', '[']) {
+        glob::glob(path)
+            .context(format!("Invalid glob `{path}`"))?
+            .collect::<Result<_, _>>()
+            .context(format!("Failed to expand `{path}`"))?
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    let mut vault_files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let entries = WalkDir::new(&path)
+                .into_iter()
+                .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'));
+            for entry in entries {
+                let entry = entry.context(format!("Failed to read {}", path.display()))?;
+                if entry.file_type().is_file() && is_vault_file(entry.path()) {
+                    vault_files.push(entry.into_path());
+                }
+            }
+        } else if path.is_file() {
+            vault_files.push(path);
+        } else {
+            bail!("`{}` does not exist", path.display());
+        }
+    }
+
+    Ok(vault_files)
 }
 
-fn get_vault_password() -> Result<String> {
-    let vault_password_file = std::env::var("ANSIBLE_VAULT_PASSWORD_FILE")
-        .context("ANSIBLE_VAULT_PASSWORD_FILE is not set")?;
-    execute_command(vault_password_file.as_str(), &[], None)
-        .map(|stdout| stdout.trim().to_string())
+fn is_vault_file(path: &Path) -> bool {
+    let mut header = [0u8; VAULT_HEADER.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map(|_| header == VAULT_HEADER.as_bytes())
+        .unwrap_or(false)
 }
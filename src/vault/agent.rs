@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs::{self, DirBuilder};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration as StdDuration, Instant};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use chrono::Duration;
+use clap::{arg, ArgMatches, Command};
+
+use crate::core::parse_duration;
+use crate::vault::vault_id::PROMPT_SOURCE;
+
+const SOCKET_ENV: &str = "TDTK_AGENT_SOCK";
+const DEFAULT_LABEL: &str = "default";
+const CLEANUP_INTERVAL: StdDuration = StdDuration::from_secs(10);
+const STARTUP_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+pub fn agent_command() -> Command {
+    Command::new("agent")
+        .about("Keep vault passwords in memory for a while, like ssh-agent")
+        .subcommand_required(true)
+        .subcommand(Command::new("start")
+            .about("Start the agent in the background")
+            .arg(arg!(--ttl <DURATION> "How long passwords are kept (e.g. 30m, 8h)")
+                .value_parser(parse_duration)
+                .default_value("1h")))
+        .subcommand(Command::new("stop")
+            .about("Stop the agent, forgetting all passwords"))
+        .subcommand(Command::new("clear")
+            .about("Forget all passwords"))
+        .subcommand(Command::new("get")
+            .about("Print the prompted password of a vault ID, usable from an ansible vault password client script")
+            .arg(arg!([LABEL] "Label of the vault ID").default_value(DEFAULT_LABEL)))
+        .subcommand(Command::new("serve")
+            .hide(true)
+            .arg(arg!(--ttl <DURATION>).value_parser(parse_duration).required(true)))
+}
+
+pub fn handle_agent_command(matches: &ArgMatches) -> Result<()> {
+    let socket_path = socket_path()?;
+    match matches.subcommand() {
+        Some(("start", matches)) => start(&socket_path, matches.get_one::<Duration>("ttl").unwrap()),
+        Some(("stop", _)) => {
+            request(&socket_path, "STOP")?;
+            println!("Stopped the vault agent");
+            Ok(())
+        }
+        Some(("clear", _)) => {
+            request(&socket_path, "CLEAR")?;
+            println!("The vault agent forgot all passwords");
+            Ok(())
+        }
+        Some(("get", matches)) => {
+            let label = matches.get_one::<String>("LABEL").unwrap();
+            let password = hex::decode(request(&socket_path, &format!("GET {label} {}", hex::encode(PROMPT_SOURCE)))?)
+                .context("Unexpected response from the vault agent")?;
+            println!("{}", String::from_utf8(password).context("UTF-8 password expected")?);
+            Ok(())
+        }
+        Some(("serve", matches)) => serve(&socket_path, *matches.get_one::<Duration>("ttl").unwrap()),
+        _ => unreachable!(),
+    }
+}
+
+/// The password of a vault ID from a running agent, `None` if there is no agent or it doesn't know the password.
+/// Passwords are kept per label and source, the password file or `prompt`, so repos sharing a label don't mix them up.
+/// Like `SSH_AUTH_SOCK`, the agent is only used when `$TDTK_AGENT_SOCK` is set.
+pub fn agent_password(label: Option<&str>, source: &str) -> Option<String> {
+    let socket_path = std::env::var_os(SOCKET_ENV).map(PathBuf::from)?;
+    let response = request(&socket_path, &format!("GET {}", agent_key(label, source))).ok()?;
+    String::from_utf8(hex::decode(response).ok()?).ok()
+}
+
+/// Hands a password to a running agent, without `$TDTK_AGENT_SOCK` nothing happens.
+pub fn store_agent_password(label: Option<&str>, source: &str, password: &str) {
+    if let Some(socket_path) = std::env::var_os(SOCKET_ENV).map(PathBuf::from) {
+        let _ = request(&socket_path, &format!("SET {} {}", agent_key(label, source), hex::encode(password)));
+    }
+}
+
+/// `<label> <hex source>`, the source is hex encoded as paths may contain spaces.
+fn agent_key(label: Option<&str>, source: &str) -> String {
+    format!("{} {}", label.unwrap_or(DEFAULT_LABEL), hex::encode(source))
+}
+
+/// `$TDTK_AGENT_SOCK`, or for the agent commands a socket in a directory only the current user can access.
+fn socket_path() -> Result<PathBuf> {
+    if let Some(socket_path) = std::env::var_os(SOCKET_ENV) {
+        return Ok(PathBuf::from(socket_path));
+    }
+    let dir = match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) => Path::new(&runtime_dir).join("tdtk"),
+        Err(_) => std::env::temp_dir().join(format!("tdtk-{}", std::env::var("USER").unwrap_or_default())),
+    };
+    Ok(dir.join("agent.sock"))
+}
+
+/// The socket and its directory must belong to the current user, and nobody else may access the directory,
+/// so passwords are never sent to or taken from someone else's process.
+fn check_socket(socket_path: &Path) -> Result<()> {
+    let dir = socket_path.parent().context("Invalid agent socket path")?;
+    check_private_dir(dir)?;
+    let metadata = fs::symlink_metadata(socket_path)
+        .context(format!("No vault agent running on {}", socket_path.display()))?;
+    if metadata.uid() != current_uid() {
+        bail!("{} is not owned by the current user", socket_path.display());
+    }
+    Ok(())
+}
+
+fn check_private_dir(dir: &Path) -> Result<()> {
+    let metadata = fs::metadata(dir).context(format!("Failed to read {}", dir.display()))?;
+    if metadata.uid() != current_uid() || metadata.permissions().mode() & 0o777 != 0o700 {
+        bail!("{} must be owned by the current user with mode 0700", dir.display());
+    }
+    Ok(())
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+fn request(socket_path: &Path, command: &str) -> Result<String> {
+    check_socket(socket_path)?;
+    let mut stream = UnixStream::connect(socket_path)
+        .context(format!("No vault agent running on {}", socket_path.display()))?;
+    writeln!(stream, "{command}").context("Failed to talk to the vault agent")?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).context("Failed to talk to the vault agent")?;
+    match response.trim_end().split_once(' ') {
+        Some(("OK", value)) => Ok(value.to_string()),
+        Some(("ERR", error)) => bail!("{error}"),
+        _ if response.trim_end() == "OK" => Ok(String::new()),
+        _ => bail!("Unexpected response from the vault agent"),
+    }
+}
+
+fn start(socket_path: &Path, ttl: &Duration) -> Result<()> {
+    if UnixStream::connect(socket_path).is_ok() {
+        bail!("The vault agent is already running on {}", socket_path.display());
+    }
+
+    let executable = std::env::current_exe().context("Failed to find the tdtk executable")?;
+    Process::new(executable)
+        .args(["vault", "agent", "serve", "--ttl", &format!("{}m", ttl.num_minutes().max(1))])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the vault agent")?;
+
+    let started = Instant::now();
+    while UnixStream::connect(socket_path).is_err() {
+        if started.elapsed() > STARTUP_TIMEOUT {
+            bail!("The vault agent did not start");
+        }
+        thread::sleep(StdDuration::from_millis(50));
+    }
+
+    println!("Started the vault agent, passwords are kept for {}m. Use it in this shell with", ttl.num_minutes());
+    println!("export {SOCKET_ENV}={}", socket_path.display());
+    Ok(())
+}
+
+struct CachedPassword {
+    password: String,
+    expires: Instant,
+}
+
+type Passwords = Arc<Mutex<HashMap<String, CachedPassword>>>;
+
+fn serve(socket_path: &Path, ttl: Duration) -> Result<()> {
+    let dir = socket_path.parent().context("Invalid agent socket path")?;
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .context(format!("Failed to create {}", dir.display()))?;
+    check_private_dir(dir)?;
+    let _ = fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path)
+        .context(format!("Failed to listen on {}", socket_path.display()))?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+
+    let ttl = ttl.to_std().context("Invalid TTL")?;
+    let passwords: Passwords = Arc::default();
+    let expiring = passwords.clone();
+    thread::spawn(move || loop {
+        thread::sleep(CLEANUP_INTERVAL);
+        expiring.lock().unwrap().retain(|_, cached| cached.expires > Instant::now());
+    });
+
+    for stream in listener.incoming().flatten() {
+        let stop = handle_request(&stream, &passwords, ttl).unwrap_or(false);
+        if stop {
+            break;
+        }
+    }
+    let _ = fs::remove_file(socket_path);
+    Ok(())
+}
+
+/// Answers one request, returns whether the agent should stop.
+fn handle_request(mut stream: &UnixStream, passwords: &Passwords, ttl: StdDuration) -> Result<bool> {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let mut parts = line.trim_end().splitn(4, ' ');
+
+    let mut passwords = passwords.lock().unwrap();
+    let (response, stop) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("GET"), Some(label), Some(source), None) => match passwords.get(&format!("{label} {source}")) {
+            Some(cached) if cached.expires > Instant::now() => (format!("OK {}", cached.password), false),
+            _ => (format!("ERR No password for vault ID `{label}`"), false),
+        },
+        (Some("SET"), Some(label), Some(source), Some(password)) => {
+            passwords.insert(format!("{label} {source}"), CachedPassword {
+                password: password.to_string(),
+                expires: Instant::now() + ttl,
+            });
+            ("OK".to_string(), false)
+        }
+        (Some("CLEAR"), None, None, None) => {
+            passwords.clear();
+            ("OK".to_string(), false)
+        }
+        (Some("STOP"), None, None, None) => ("OK".to_string(), true),
+        _ => ("ERR Unknown request".to_string(), false),
+    };
+
+    writeln!(stream, "{response}")?;
+    Ok(stop)
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+const DEFAULTS_SECTION: &str = "defaults";
+
+/// The `[defaults]` section of the `ansible.cfg` ansible itself would use.
+pub struct AnsibleConfig {
+    path: PathBuf,
+    defaults: BTreeMap<String, String>,
+}
+
+impl AnsibleConfig {
+    /// Looks in the same places as ansible: `$ANSIBLE_CONFIG`, `./ansible.cfg`, `~/.ansible.cfg`, `/etc/ansible/ansible.cfg`.
+    pub fn load() -> Result<Option<Self>> {
+        let mut candidates: Vec<PathBuf> = std::env::var_os("ANSIBLE_CONFIG").map(PathBuf::from).into_iter().collect();
+        candidates.push(PathBuf::from("ansible.cfg"));
+        if let Some(home_dir) = dirs::home_dir() {
+            candidates.push(home_dir.join(".ansible.cfg"));
+        }
+        candidates.push(PathBuf::from("/etc/ansible/ansible.cfg"));
+
+        match candidates.into_iter().find(|path| path.is_file()) {
+            Some(path) => {
+                let content = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+                Ok(Some(AnsibleConfig { defaults: parse_defaults(&content), path }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.defaults.get(key).map(String::as_str).filter(|value| !value.is_empty())
+    }
+
+    /// Relative paths in `ansible.cfg` are relative to its directory, like in ansible.
+    pub fn resolve(&self, value: &str) -> PathBuf {
+        let path = expand_home(value);
+        if path.is_absolute() {
+            return path;
+        }
+        match self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => dir.join(path),
+            None => path,
+        }
+    }
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home_dir)) => home_dir.join(rest),
+        _ => Path::new(path).to_path_buf(),
+    }
+}
+
+fn parse_defaults(content: &str) -> BTreeMap<String, String> {
+    let mut defaults = BTreeMap::new();
+    let mut in_defaults = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            in_defaults = section.trim() == DEFAULTS_SECTION;
+            continue;
+        }
+        if !in_defaults {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(['=', ':']) {
+            defaults.insert(key.trim().to_string(), strip_inline_comment(value.trim()).to_string());
+        }
+    }
+    defaults
+}
+
+/// ansible.cfg allows `# comment` and `; comment` after a value when preceded by whitespace.
+fn strip_inline_comment(value: &str) -> &str {
+    [" #", " ;", "\t#", "\t;"].iter()
+        .filter_map(|marker| value.find(marker))
+        .min()
+        .map(|index| value[..index].trim_end())
+        .unwrap_or(value)
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use clap::{arg, value_parser, ArgMatches, Command, ValueEnum};
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::vault::{decrypt_vars_file, find_vault_files};
+use crate::vault::key_path::KeyPath;
+use crate::vault::vault_id::VaultIds;
+
+const DEFAULT_MIN_LENGTH: usize = 12;
+const MIN_ENTROPY_BITS: f64 = 60.0;
+const COMMON_PASSWORDS: [&str; 12] = [
+    "password", "passw0rd", "changeme", "secret", "admin", "root",
+    "123456", "12345678", "qwerty", "letmein", "welcome", "default",
+];
+
+/// Exit code if there are findings.
+const EXIT_FINDINGS: i32 = 1;
+/// Exit code if vault files can't be decrypted, which hides all other findings in them.
+const EXIT_UNDECRYPTABLE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Check {
+    Decrypt,
+    Naming,
+    Duplicate,
+    Weak,
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    file: String,
+    key: Option<String>,
+    check: Check,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Report {
+    files: usize,
+    findings: Vec<Finding>,
+}
+
+pub fn audit_command() -> Command {
+    Command::new("audit")
+        .about("Check all vault files for decryption failures, naming, duplicated and weak secrets")
+        .arg(arg!([DIR] "Directory to search for vault files").default_value("."))
+        .arg(arg!(--format <FORMAT> "Output format")
+            .value_parser(value_parser!(Format))
+            .default_value("table"))
+        .arg(arg!(--"min-length" <LENGTH> "Secrets shorter than this are reported as weak")
+            .value_parser(value_parser!(usize))
+            .default_value(DEFAULT_MIN_LENGTH.to_string()))
+}
+
+/// Exits with 1 if there are findings and 2 if some vault files could not be decrypted.
+pub fn handle_audit_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let dir = matches.get_one::<String>("DIR").unwrap();
+    let min_length = *matches.get_one::<usize>("min-length").unwrap();
+    let vault_files = find_vault_files(dir)?;
+    let vault = vault_ids.passwords()?;
+
+    let mut findings = Vec::new();
+    let mut locations: BTreeMap<String, Vec<(String, KeyPath)>> = BTreeMap::new();
+    for path in &vault_files {
+        let file = path.display().to_string();
+        let document = match decrypt_vars_file(&file, &vault) {
+            Ok(Some(document)) => document,
+            Ok(None) => {
+                eprintln!("Skipping {file}, it doesn't contain YAML vars");
+                continue;
+            }
+            Err(e) => {
+                findings.push(Finding { file, key: None, check: Check::Decrypt, message: format!("{e:#}") });
+                continue;
+            }
+        };
+
+        for (key, value) in KeyPath::leaves(document.values()) {
+            let normalized = KeyPath::normalized(&key.to_string());
+            if normalized != key {
+                findings.push(Finding {
+                    file: file.clone(),
+                    key: Some(key.to_string()),
+                    check: Check::Naming,
+                    message: format!("Expected `{normalized}`"),
+                });
+            }
+
+            let Value::String(secret) = value else { continue };
+            if let Some(weakness) = weakness(secret, min_length) {
+                findings.push(Finding { file: file.clone(), key: Some(key.to_string()), check: Check::Weak, message: weakness });
+            }
+            locations.entry(secret.clone()).or_default().push((file.clone(), key));
+        }
+    }
+
+    for duplicates in locations.values().filter(|locations| locations.len() > 1) {
+        for (index, (file, key)) in duplicates.iter().enumerate() {
+            let others: Vec<String> = duplicates.iter().enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, (file, key))| format!("{file}:{key}"))
+                .collect();
+            findings.push(Finding {
+                file: file.clone(),
+                key: Some(key.to_string()),
+                check: Check::Duplicate,
+                message: format!("Same value as {}", others.join(", ")),
+            });
+        }
+    }
+    findings.sort_by(|a, b| (&a.file, &a.key).cmp(&(&b.file, &b.key)));
+
+    let undecryptable = findings.iter().any(|finding| finding.check == Check::Decrypt);
+    let has_findings = !findings.is_empty();
+    let report = Report { files: vault_files.len(), findings };
+    match matches.get_one::<Format>("format").unwrap() {
+        Format::Table => print_table(&report),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+
+    if undecryptable {
+        std::process::exit(EXIT_UNDECRYPTABLE);
+    }
+    if has_findings {
+        std::process::exit(EXIT_FINDINGS);
+    }
+    Ok(())
+}
+
+fn weakness(secret: &str, min_length: usize) -> Option<String> {
+    let length = secret.chars().count();
+    if COMMON_PASSWORDS.contains(&secret.to_lowercase().as_str()) {
+        return Some("Commonly used password".to_string());
+    }
+    if length < min_length {
+        return Some(format!("Only {length} characters long, expected at least {min_length}"));
+    }
+    let entropy = entropy_bits(secret);
+    if entropy < MIN_ENTROPY_BITS {
+        return Some(format!("Only about {entropy:.0} bits of entropy, expected at least {MIN_ENTROPY_BITS:.0}"));
+    }
+    None
+}
+
+/// A rough upper bound of the entropy from the length and the character classes used.
+fn entropy_bits(secret: &str) -> f64 {
+    let uses = |is_class: fn(&char) -> bool| secret.chars().any(|c| is_class(&c));
+    let pool = [
+        (uses(char::is_ascii_lowercase), 26),
+        (uses(char::is_ascii_uppercase), 26),
+        (uses(char::is_ascii_digit), 10),
+        (uses(|c| !c.is_ascii_alphanumeric()), 33),
+    ].iter().filter(|(used, _)| *used).map(|(_, size)| size).sum::<usize>();
+    let distinct = {
+        let mut chars: Vec<char> = secret.chars().collect();
+        chars.sort_unstable();
+        chars.dedup();
+        chars.len()
+    };
+    // Repeating a few characters doesn't add much, e.g. `aaaaaaaaaaaaaaaa`
+    (pool.max(1) as f64).log2() * secret.chars().count().min(distinct * 4) as f64
+}
+
+fn print_table(report: &Report) {
+    if report.findings.is_empty() {
+        println!("No findings in {} vault files", report.files);
+        return;
+    }
+
+    let rows: Vec<[String; 4]> = report.findings.iter()
+        .map(|finding| [
+            finding.file.clone(),
+            finding.key.clone().unwrap_or_default(),
+            format!("{:?}", finding.check).to_lowercase(),
+            finding.message.clone(),
+        ])
+        .collect();
+    let header = ["FILE", "KEY", "CHECK", "MESSAGE"].map(str::to_string);
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+
+    for row in std::iter::once(&header).chain(&rows) {
+        println!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+    }
+    println!("{} findings in {} vault files", report.findings.len(), report.files);
+}
@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::vault::key_path::KeyPath;
+use crate::vault::yaml_edit::{remove_path, rename_path, set_path};
+
+/// Decrypted vault content that is edited in place, so key order, comments and formatting
+/// of everything but the changed entries survive a rewrite.
+pub struct VaultDocument {
+    content: String,
+    values: Mapping,
+}
+
+impl VaultDocument {
+    pub fn parse(content: String) -> Result<Self> {
+        let values = parse_mapping(&content)?;
+        Ok(VaultDocument { content, values })
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn values(&self) -> &Mapping {
+        &self.values
+    }
+
+    pub fn get(&self, path: &KeyPath) -> Option<&Value> {
+        path.get(&self.values)
+    }
+
+    /// Sets the value at `path` and returns the previous value.
+    pub fn set(&mut self, path: &KeyPath, value: Value) -> Result<Option<Value>> {
+        let mut values = self.values.clone();
+        let previous = path.set(&mut values, value.clone())?;
+        // Entries inside flow style mappings can't be edited, their closest editable parent is rewritten instead
+        let content = path.ancestors().iter()
+            .filter_map(|ancestor| set_path(&self.content, ancestor.segments(), ancestor.get(&values)?))
+            .find(|content| has_values(content, &values));
+        self.update(content, values)?;
+        Ok(previous)
+    }
+
+    /// Removes the value at `path` and returns it, `None` if there is none.
+    pub fn remove(&mut self, path: &KeyPath) -> Result<Option<Value>> {
+        let mut values = self.values.clone();
+        let Some(previous) = path.remove(&mut values) else { return Ok(None) };
+        let content = remove_path(&self.content, path.segments())
+            .filter(|content| has_values(content, &values))
+            .or_else(|| path.ancestors().iter().skip(1)
+                .filter_map(|ancestor| set_path(&self.content, ancestor.segments(), ancestor.get(&values)?))
+                .find(|content| has_values(content, &values)));
+        self.update(content, values)?;
+        Ok(Some(previous))
+    }
+
+    /// Moves the value at `from` to `to`, in place if both have the same parent.
+    pub fn rename(&mut self, from: &KeyPath, to: &KeyPath) -> Result<()> {
+        let value = match self.get(from) {
+            Some(value) => value.clone(),
+            None => bail!("`{from}` not found"),
+        };
+        if from.parent() != to.parent() {
+            self.remove(from)?;
+            self.set(to, value)?;
+            return Ok(());
+        }
+
+        let mut values = self.values.clone();
+        from.remove(&mut values);
+        to.set(&mut values, value)?;
+        let content = rename_path(&self.content, from.segments(), to.leaf());
+        self.update(content, values)
+    }
+
+    /// Takes the edited content if it has exactly the expected values,
+    /// otherwise the whole document is rendered again, with a warning as comments and formatting are lost.
+    fn update(&mut self, content: Option<String>, values: Mapping) -> Result<()> {
+        self.content = match content {
+            Some(content) if has_values(&content, &values) => content,
+            _ => {
+                eprintln!("Warning: the change could not be made in place, the vault file is rewritten without its comments and formatting");
+                serde_yaml::to_string(&values).context("Failed to render the vault file")?
+            }
+        };
+        self.values = values;
+        Ok(())
+    }
+}
+
+fn has_values(content: &str, values: &Mapping) -> bool {
+    parse_mapping(content).ok().as_ref() == Some(values)
+}
+
+fn parse_mapping(content: &str) -> Result<Mapping> {
+    let document: Value = serde_yaml::from_str(content)
+        .context("Failed to parse decrypted vault file")?;
+    match document {
+        Value::Null => Ok(Mapping::new()),
+        Value::Mapping(mapping) => Ok(mapping),
+        _ => bail!("The decrypted vault file is not a YAML mapping"),
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command as Process;
+
+use anyhow::{bail, Context, Result};
+use clap::{arg, ArgMatches, Command};
+use rand::distributions::{Alphanumeric, DistString};
+
+use crate::config::Config;
+use crate::core::confirm;
+use crate::vault::{decrypt_vault_file, write_vault_file};
+use crate::vault::document::VaultDocument;
+use crate::vault::storage::lock_vault_file;
+use crate::vault::vault_id::VaultIds;
+
+const DEFAULT_EDITOR: &str = "vi";
+/// Shared memory is not written to disk, so the plaintext never touches it.
+const TMPFS_DIR: &str = "/dev/shm";
+
+pub fn edit_command() -> Command {
+    Command::new("edit")
+        .about("Edit the decrypted content of a vault file in $EDITOR")
+        .arg(arg!(<FILE> "The vault file to edit"))
+}
+
+pub fn handle_edit_command(matches: &ArgMatches, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("FILE").unwrap();
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+
+    let temp_file = PlaintextFile::create(vault_file.content())?;
+    let edited = loop {
+        open_editor(temp_file.path())?;
+        let content = fs::read_to_string(temp_file.path())
+            .context(format!("Failed to read {}", temp_file.path().display()))?;
+        match VaultDocument::parse(content) {
+            Ok(edited) => break edited,
+            Err(e) => {
+                eprintln!("{e:#}");
+                if !confirm("The vault content is not valid YAML. Edit it again?")? {
+                    bail!("Discarded the changes, {vault_file_path} was not changed");
+                }
+            }
+        }
+    };
+
+    if edited.content() == vault_file.content() {
+        println!("No changes, {vault_file_path} was not changed");
+        return Ok(());
+    }
+    write_vault_file(vault_file_path, &edited, &vault, config.vault_backups)?;
+    println!("Saved {vault_file_path}");
+    Ok(())
+}
+
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    let mut command = editor.split_whitespace();
+    let program = command.next().unwrap_or(DEFAULT_EDITOR);
+
+    let status = Process::new(program)
+        .args(command)
+        .arg(path)
+        .status()
+        .context(format!("Failed to start editor `{editor}`"))?;
+    if !status.success() {
+        bail!("Editor `{editor}` exited with {status}, the vault file was not changed");
+    }
+    Ok(())
+}
+
+/// A temp file only readable by the current user, overwritten and deleted when dropped.
+struct PlaintextFile {
+    path: PathBuf,
+}
+
+impl PlaintextFile {
+    fn create(content: &str) -> Result<Self> {
+        let dir = if Path::new(TMPFS_DIR).is_dir() { PathBuf::from(TMPFS_DIR) } else { std::env::temp_dir() };
+        let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+        let path = dir.join(format!("tdtk-vault-{name}.yml"));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .context(format!("Failed to create {}", path.display()))?;
+        let temp_file = PlaintextFile { path };
+        file.write_all(content.as_bytes())
+            .context(format!("Failed to write {}", temp_file.path.display()))?;
+        Ok(temp_file)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PlaintextFile {
+    fn drop(&mut self) {
+        // Editors may replace the file, so the current length is overwritten
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if let Ok(mut file) = OpenOptions::new().write(true).open(&self.path) {
+                let _ = file.write_all(&vec![0; metadata.len() as usize]).and_then(|_| file.sync_all());
+            }
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,105 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command as Process;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use clap::{arg, ArgMatches, Command};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::vault::decrypt_vault_file;
+use crate::vault::export::env_var_name;
+use crate::vault::key_path::scalar_to_string;
+use crate::vault::vault_id::VaultIds;
+
+/// Exit code convention of shells for processes killed by a signal.
+const SIGNAL_EXIT_OFFSET: i32 = 128;
+
+pub fn exec_command() -> Command {
+    Command::new("exec")
+        .about("Run a command with the secrets of a vault file as environment variables")
+        .arg(arg!(-f --file <FILE> "The vault file with the secrets").required(true))
+        .arg(arg!(--"strip-prefix" "Remove the `vault_` prefix, e.g. `vault_db_password` becomes `DB_PASSWORD`"))
+        .arg(arg!(<COMMAND> ... "The command to run, after `--`")
+            .trailing_var_arg(true)
+            .allow_hyphen_values(true))
+}
+
+/// Exits with the exit code of the command, the secrets never touch the disk.
+pub fn handle_exec_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("file").unwrap();
+    let strip_prefix = matches.get_flag("strip-prefix");
+    let mut command = matches.get_many::<String>("COMMAND").unwrap();
+    let program = command.next().unwrap();
+
+    let vault = vault_ids.passwords()?;
+    let vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+    let env = vault_file.values().iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_str()?;
+            let name = if strip_prefix { name.strip_prefix("vault_").unwrap_or(name) } else { name };
+            Some((env_var_name(name), scalar_to_string(value)))
+        });
+
+    let forwarding = forward_signals()?;
+    let mut child = Process::new(program)
+        .args(command)
+        .envs(env)
+        .spawn()
+        .context(format!("Failed to execute process `{program}`"))?;
+    forwarding.started(child.id() as i32);
+
+    let status = child.wait().context(format!("Failed to wait for `{program}`"))?;
+    let code = match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => SIGNAL_EXIT_OFFSET + signal,
+        (None, None) => 1,
+    };
+    std::process::exit(code)
+}
+
+/// The child signals are forwarded to, signals received before it started are held back until then.
+#[derive(Clone, Default)]
+struct Forwarding(Arc<Mutex<(Option<i32>, Vec<i32>)>>);
+
+impl Forwarding {
+    fn started(&self, pid: i32) {
+        let mut state = self.0.lock().unwrap();
+        state.0 = Some(pid);
+        for signal in std::mem::take(&mut state.1) {
+            send_signal(pid, signal);
+        }
+    }
+
+    fn forward(&self, signal: i32) {
+        let mut state = self.0.lock().unwrap();
+        match state.0 {
+            Some(pid) => send_signal(pid, signal),
+            None => state.1.push(signal),
+        }
+    }
+}
+
+/// Passes signals on to the child, installed before it is spawned so none of them ends tdtk first.
+/// Ctrl-C and Ctrl-\ from the terminal reach the child directly as well, so it sees those twice.
+fn forward_signals() -> Result<Forwarding> {
+    let forwarding = Forwarding::default();
+    let kinds = [
+        SignalKind::interrupt(), SignalKind::quit(), SignalKind::terminate(),
+        SignalKind::hangup(), SignalKind::user_defined1(), SignalKind::user_defined2(),
+    ];
+    for kind in kinds {
+        let mut signals = signal(kind).context("Failed to handle signals")?;
+        let forwarding = forwarding.clone();
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                forwarding.forward(kind.as_raw_value());
+            }
+        });
+    }
+    Ok(forwarding)
+}
+
+fn send_signal(pid: i32, signal: i32) {
+    // Safety: kill only sends a signal, it has no memory effects
+    unsafe { libc::kill(pid, signal); }
+}
@@ -0,0 +1,182 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command, ValueEnum};
+use glob::Pattern;
+use serde_yaml::{Mapping, Value};
+
+use crate::vault::decrypt_vault_file;
+use crate::vault::key_path::scalar_to_string;
+use crate::vault::vault_id::VaultIds;
+
+const DEFAULT_SECRET_NAME: &str = "secrets";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Env,
+    Json,
+    Yaml,
+    K8sSecret,
+    DockerComposeSecrets,
+}
+
+pub fn export_command() -> Command {
+    Command::new("export")
+        .about("Export the secrets of a vault file for deployments and local runs")
+        .arg(arg!(<FILE> "The vault file to export"))
+        .arg(arg!(--format <FORMAT> "Output format")
+            .value_parser(value_parser!(ExportFormat))
+            .required(true))
+        .arg(arg!(--"strip-prefix" "Remove the `vault_` prefix from the names"))
+        .arg(arg!(--filter <PATTERN> "Only export secrets whose name matches the glob (e.g. 'vault_db_*')")
+            .value_parser(Pattern::new)
+            .action(ArgAction::Append))
+        .arg(arg!(-o --output <PATH> "File to write to with 0600 permissions, a directory for docker-compose-secrets"))
+        .arg(arg!(--name <NAME> "Name of the Kubernetes secret, the vault file's directory name by default"))
+        .arg(arg!(--force "Write secrets to stdout even if it is not a terminal"))
+}
+
+pub fn handle_export_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("FILE").unwrap();
+    let format = *matches.get_one::<ExportFormat>("format").unwrap();
+    let output = matches.get_one::<String>("output");
+    if format == ExportFormat::DockerComposeSecrets && output.is_none() {
+        bail!("docker-compose-secrets writes one file per secret, pass the directory with --output");
+    }
+    if output.is_none() && !io::stdout().is_terminal() && !matches.get_flag("force") {
+        bail!("Refusing to write secrets to stdout, it is not a terminal. Use --output or --force");
+    }
+
+    let vault = vault_ids.passwords()?;
+    let vault_file = decrypt_vault_file(vault_file_path, &vault)?;
+    let filters: Vec<&Pattern> = matches.get_many::<Pattern>("filter").unwrap_or_default().collect();
+    let strip_prefix = matches.get_flag("strip-prefix");
+
+    let mut secrets = Mapping::new();
+    for (name, value) in vault_file.values() {
+        let Some(name) = name.as_str() else { continue };
+        let stripped = name.strip_prefix("vault_").unwrap_or(name);
+        if !filters.is_empty() && !filters.iter().any(|filter| filter.matches(name) || filter.matches(stripped)) {
+            continue;
+        }
+        let name = if strip_prefix { stripped } else { name };
+        secrets.insert(Value::String(name.to_string()), value.clone());
+    }
+
+    let exported = match format {
+        ExportFormat::Env => to_env(&secrets),
+        ExportFormat::Json => serde_json::to_string_pretty(&secrets)? + "\n",
+        ExportFormat::Yaml => serde_yaml::to_string(&secrets)?,
+        ExportFormat::K8sSecret => {
+            let name = match matches.get_one::<String>("name") {
+                Some(name) => name.clone(),
+                None => default_secret_name(vault_file_path),
+            };
+            to_k8s_secret(&name, &secrets)?
+        }
+        ExportFormat::DockerComposeSecrets => {
+            let dir = Path::new(output.unwrap());
+            let compose = write_compose_secrets(dir, &secrets)?;
+            // The compose snippet only references the secret files, so it is fine on any stdout
+            print!("{compose}");
+            return Ok(());
+        }
+    };
+
+    match output {
+        Some(output) => {
+            write_private_file(Path::new(output), exported.as_bytes())?;
+            eprintln!("Exported {} secrets to {output}", secrets.len());
+        }
+        None => print!("{exported}"),
+    }
+    Ok(())
+}
+
+/// Env variable names are upper case, e.g. `vault_db_password` becomes `VAULT_DB_PASSWORD`.
+pub fn env_var_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+fn to_env(secrets: &Mapping) -> String {
+    secrets.iter()
+        .map(|(name, value)| {
+            let value = scalar_to_string(value)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{value}\"\n", env_var_name(&scalar_to_string(name)))
+        })
+        .collect()
+}
+
+fn to_k8s_secret(name: &str, secrets: &Mapping) -> Result<String> {
+    let mut data = Mapping::new();
+    for (key, value) in secrets {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(scalar_to_string(value));
+        data.insert(key.clone(), Value::String(encoded));
+    }
+
+    let mut metadata = Mapping::new();
+    metadata.insert("name".into(), name.into());
+
+    let mut manifest = Mapping::new();
+    manifest.insert("apiVersion".into(), "v1".into());
+    manifest.insert("kind".into(), "Secret".into());
+    manifest.insert("metadata".into(), Value::Mapping(metadata));
+    manifest.insert("type".into(), "Opaque".into());
+    manifest.insert("data".into(), Value::Mapping(data));
+    Ok(serde_yaml::to_string(&manifest)?)
+}
+
+/// Kubernetes names are lower case, e.g. `group_vars/prod/vault.yml` becomes `prod`.
+fn default_secret_name(vault_file_path: &str) -> String {
+    let name: String = fs::canonicalize(vault_file_path).ok()
+        .and_then(|path| path.parent().and_then(|dir| dir.file_name()).map(|dir| dir.to_string_lossy().to_string()))
+        .unwrap_or_default()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect();
+    let name = name.trim_matches(['-', '.']).to_string();
+    if name.is_empty() { DEFAULT_SECRET_NAME.to_string() } else { name }
+}
+
+/// Writes every secret to its own file and returns the compose `secrets` section referencing them.
+fn write_compose_secrets(dir: &Path, secrets: &Mapping) -> Result<String> {
+    fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+
+    let mut compose_secrets = Mapping::new();
+    for (name, value) in secrets {
+        let name = scalar_to_string(name);
+        let path = dir.join(&name);
+        write_private_file(&path, scalar_to_string(value).as_bytes())?;
+
+        let mut file = Mapping::new();
+        file.insert("file".into(), path.display().to_string().into());
+        compose_secrets.insert(Value::String(name), Value::Mapping(file));
+    }
+
+    let mut compose = Mapping::new();
+    compose.insert("secrets".into(), Value::Mapping(compose_secrets));
+    Ok(serde_yaml::to_string(&compose)?)
+}
+
+pub fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .context(format!("Failed to create {}", path.display()))?;
+    // An existing file keeps its permissions on open, so they are tightened explicitly
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content).context(format!("Failed to write {}", path.display()))
+}
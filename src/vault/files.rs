@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use clap::{arg, ArgMatches, Command};
+
+use crate::vault::VAULT_HEADER;
+use crate::vault::export::write_private_file;
+use crate::vault::storage::write_atomically;
+use crate::vault::vault_id::VaultIds;
+
+const VAULT_EXTENSION: &str = "vault";
+const STDIO: &str = "-";
+
+pub fn file_commands() -> [Command; 2] {
+    [
+        Command::new("encrypt-file")
+            .about("Encrypt a whole file, e.g. a private key or keystore, binary content is kept as is")
+            .arg(arg!(<FILE> "The file to encrypt, `-` reads from stdin"))
+            .arg(arg!(-o --output <OUTPUT> "Where to write the vault, `<FILE>.vault` by default, `-` writes to stdout"))
+            .arg(arg!(--force "Overwrite an existing output file")),
+        Command::new("decrypt-file")
+            .about("Decrypt a whole-file vault, the output is only readable by you")
+            .arg(arg!(<FILE> "The vault to decrypt, `-` reads from stdin"))
+            .arg(arg!(-o --output <OUTPUT> "Where to write the content, FILE without `.vault` by default, `-` writes to stdout"))
+            .arg(arg!(--force "Overwrite an existing output file")),
+    ]
+}
+
+/// Encrypts with the encrypt vault ID, like `ansible-vault encrypt` but into a separate file.
+pub fn handle_encrypt_file_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let input = matches.get_one::<String>("FILE").unwrap();
+    let output = match matches.get_one::<String>("output") {
+        Some(output) => output.clone(),
+        None if input == STDIO => bail!("Reading from stdin, pass --output"),
+        None => format!("{input}.{VAULT_EXTENSION}"),
+    };
+    let content = read_input(input)?;
+    if content.starts_with(VAULT_HEADER.as_bytes()) {
+        bail!("{input} is encrypted already");
+    }
+    check_output(&output, matches.get_flag("force"))?;
+
+    let vault = vault_ids.passwords()?;
+    let encrypted = vault.encrypt(&content)?;
+    if output == STDIO {
+        return io::stdout().write_all(encrypted.as_bytes()).context("Failed to write to stdout");
+    }
+    write_atomically(Path::new(&output), encrypted.as_bytes())?;
+    eprintln!("Encrypted {input} to {output}");
+    Ok(())
+}
+
+pub fn handle_decrypt_file_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let input = matches.get_one::<String>("FILE").unwrap();
+    let output = match matches.get_one::<String>("output") {
+        Some(output) => output.clone(),
+        None => match Path::new(input).extension() {
+            Some(extension) if extension == VAULT_EXTENSION => input.strip_suffix(&format!(".{VAULT_EXTENSION}")).unwrap().to_string(),
+            _ => bail!("{input} has no `.{VAULT_EXTENSION}` extension, pass --output"),
+        },
+    };
+    let content = read_input(input)?;
+    check_output(&output, matches.get_flag("force"))?;
+
+    let vault = vault_ids.passwords()?;
+    let decrypted = vault.decrypt(&content).context(format!("Failed to decrypt {input}"))?;
+    if output == STDIO {
+        return io::stdout().write_all(&decrypted).context("Failed to write to stdout");
+    }
+    write_private_file(Path::new(&output), &decrypted)?;
+    eprintln!("Decrypted {input} to {output}");
+    Ok(())
+}
+
+/// Reads a file to store as a secret, binary content only base64 encoded since YAML values are text.
+pub fn read_secret_file(path: &str, base64: bool) -> Result<String> {
+    let content = fs::read(path).context(format!("Failed to read {path}"))?;
+    if base64 {
+        return Ok(base64::engine::general_purpose::STANDARD.encode(content));
+    }
+    String::from_utf8(content)
+        .with_context(|| format!("{path} is not a text file, pass --base64 to store it base64 encoded"))
+}
+
+fn read_input(input: &str) -> Result<Vec<u8>> {
+    if input == STDIO {
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content).context("Failed to read from stdin")?;
+        return Ok(content);
+    }
+    fs::read(input).context(format!("Failed to read {input}"))
+}
+
+fn check_output(output: &str, force: bool) -> Result<()> {
+    if output != STDIO && !force && Path::new(output).exists() {
+        bail!("{output} exists already, pass --force to overwrite it");
+    }
+    Ok(())
+}
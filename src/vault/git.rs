@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use clap::{arg, ArgAction, ArgMatches, Command};
+
+use crate::core::capture_command;
+use crate::vault::{decrypt_vault_file, write_vault_file};
+use crate::vault::document::VaultDocument;
+use crate::vault::key_path::KeyPath;
+use crate::vault::storage::SIBLING_PATTERNS;
+use crate::vault::vault_id::{VaultIds, VaultPasswords};
+
+const DRIVER_NAME: &str = "ansible-vault";
+const GITATTRIBUTES: &str = ".gitattributes";
+const GITIGNORE: &str = ".gitignore";
+
+pub fn git_commands() -> [Command; 3] {
+    [
+        Command::new("textconv")
+            .about("Print the decrypted content of a vault file, for use as git diff textconv")
+            .arg(arg!(<FILE> "The vault file to decrypt")),
+        Command::new("merge")
+            .about("Three-way merge of vault files, for use as git merge driver")
+            .arg(arg!(<BASE> "The common ancestor version (%O)"))
+            .arg(arg!(<OURS> "Our version, the merge result is written to it (%A)"))
+            .arg(arg!(<THEIRS> "Their version (%B)")),
+        Command::new("git-setup")
+            .about("Configure git to diff and merge vault files decrypted and to ignore their backups and lock files")
+            .arg(arg!(-p --pattern <PATTERN> "Patterns of vault files in .gitattributes")
+                .action(ArgAction::Append)
+                .default_values(["vault.yml", "vault.yaml"])),
+    ]
+}
+
+pub fn handle_textconv_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("FILE").unwrap();
+    let content = fs::read(vault_file_path).context(format!("Failed to read {vault_file_path}"))?;
+    if !content.starts_with(b"$ANSIBLE_VAULT;") {
+        return std::io::stdout().write_all(&content).context("Failed to write to stdout");
+    }
+
+    let vault = vault_ids.passwords()?;
+    let decrypted = vault.decrypt(&content)
+        .context(format!("Failed to decrypt {vault_file_path}"))?;
+    std::io::stdout().write_all(&decrypted).context("Failed to write to stdout")
+}
+
+/// Merges their changes into our version per value, our version keeps its comments and order.
+/// On conflicts our version is left untouched and the merge fails, so git marks the file as conflicted.
+pub fn handle_merge_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let [base_path, ours_path, theirs_path] = ["BASE", "OURS", "THEIRS"]
+        .map(|name| matches.get_one::<String>(name).unwrap());
+
+    let vault = vault_ids.passwords()?;
+    let base = decrypt_merge_version(base_path, &vault)?;
+    let mut merged = decrypt_merge_version(ours_path, &vault)?;
+    let ours = merged.values().clone();
+    let theirs = decrypt_merge_version(theirs_path, &vault)?;
+
+    let mut changed_paths: Vec<KeyPath> = Vec::new();
+    for (path, _) in KeyPath::leaves(base.values()).into_iter().chain(KeyPath::leaves(theirs.values())) {
+        if !changed_paths.contains(&path) {
+            changed_paths.push(path);
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for path in changed_paths {
+        let (base_value, our_value, their_value) = (base.get(&path), path.get(&ours), theirs.get(&path));
+        if their_value == base_value || their_value == our_value {
+            continue;
+        }
+        if our_value != base_value {
+            conflicts.push(path);
+            continue;
+        }
+        match their_value {
+            Some(value) => {
+                merged.set(&path, value.clone())?;
+            }
+            None => {
+                merged.remove(&path)?;
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        for path in &conflicts {
+            eprintln!("Conflict: `{path}` was changed on both sides");
+        }
+        bail!("Failed to merge the vault file, our version is kept, resolve the conflicts with `tdtk vault edit`");
+    }
+    write_vault_file(ours_path, &merged, &vault, 0)
+}
+
+/// Empty versions, e.g. the base of a file added on both sides, have no values.
+fn decrypt_merge_version(path: &str, vault: &VaultPasswords) -> Result<VaultDocument> {
+    let content = fs::read(path).context(format!("Failed to read {path}"))?;
+    if content.iter().all(u8::is_ascii_whitespace) {
+        return VaultDocument::parse(String::new());
+    }
+    decrypt_vault_file(path, vault)
+}
+
+pub fn handle_git_setup_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let repo_root = capture_command("git", &["rev-parse", "--show-toplevel"], None)
+        .context("Not in a git repository")?;
+    let gitattributes_path = Path::new(repo_root.trim()).join(GITATTRIBUTES);
+    let attributes = matches.get_many::<String>("pattern").unwrap()
+        .map(|pattern| format!("{pattern} diff={DRIVER_NAME} merge={DRIVER_NAME}"));
+    append_missing_lines(&gitattributes_path, attributes)?;
+    let gitignore_path = Path::new(repo_root.trim()).join(GITIGNORE);
+    append_missing_lines(&gitignore_path, SIBLING_PATTERNS.map(str::to_string))?;
+
+    let vault_id_args: String = vault_ids.to_args().iter()
+        .map(|arg| format!(" {}", shell_quote(arg)))
+        .collect();
+    let config = [
+        (format!("diff.{DRIVER_NAME}.textconv"), format!("tdtk vault{vault_id_args} textconv")),
+        (format!("merge.{DRIVER_NAME}.name"), "ansible vault merge driver".to_string()),
+        (format!("merge.{DRIVER_NAME}.driver"), format!("tdtk vault{vault_id_args} merge %O %A %B")),
+    ];
+    for (key, value) in &config {
+        capture_command("git", &["config", key, value], None)?;
+    }
+
+    println!("Updated {}, {} and the git config of {}", gitattributes_path.display(), gitignore_path.display(), repo_root.trim());
+    Ok(())
+}
+
+fn append_missing_lines(path: &Path, lines: impl IntoIterator<Item = String>) -> Result<()> {
+    let mut content = fs::read_to_string(path).unwrap_or_default();
+    for line in lines {
+        if content.lines().any(|existing| existing.trim() == line) {
+            continue;
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&line);
+        content.push('\n');
+    }
+    fs::write(path, content).context(format!("Failed to write {}", path.display()))
+}
+
+fn shell_quote(arg: &str) -> String {
+    if arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./@=:".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
@@ -0,0 +1,211 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::Duration;
+use clap::{arg, value_parser, ArgMatches, Command, ValueEnum};
+use derive_more::Display;
+use serde_yaml::{Mapping, Value};
+
+use crate::config::Config;
+use crate::core::{parse_duration, select};
+use crate::vault::{decrypt_vault_file, normalize_secret_name, write_vault_file};
+use crate::vault::document::VaultDocument;
+use crate::vault::key_path::KeyPath;
+use crate::vault::metadata::update_metadata;
+use crate::vault::storage::lock_vault_file;
+use crate::vault::vault_id::VaultIds;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    Env,
+    Json,
+    Yaml,
+}
+
+/// What to do with a secret that exists already.
+#[derive(Display, Clone, Copy, ValueEnum)]
+enum Resolution {
+    #[display(fmt = "Overwrite it")]
+    Overwrite,
+    #[display(fmt = "Keep the existing value")]
+    Keep,
+    #[display(fmt = "Overwrite all remaining")]
+    OverwriteAll,
+    #[display(fmt = "Keep all remaining")]
+    KeepAll,
+}
+
+pub fn import_command() -> Command {
+    Command::new("import")
+        .about("Import many secrets at once from a .env, JSON or YAML file")
+        .arg(arg!(-f --file <FILE> "The vault file to import into, created if missing").required(true))
+        .arg(arg!(--from <SOURCE> "The file to import, `-` reads from stdin").required(true))
+        .arg(arg!(--format <FORMAT> "Format of the source, guessed from the file name or content by default")
+            .value_parser(value_parser!(ImportFormat)))
+        .arg(arg!(--overwrite "Overwrite existing secrets without asking"))
+        .arg(arg!(--"expires-in" <DURATION> "When the imported secrets expire, recorded in the secret metadata (e.g. 90d)")
+            .value_parser(parse_duration))
+        .arg(arg!(--"dry-run" "Only show what would be imported"))
+}
+
+pub fn handle_import_command(matches: &ArgMatches, vault_ids: &VaultIds, config: &Config) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("file").unwrap();
+    let source = matches.get_one::<String>("from").unwrap();
+    let content = if source == "-" {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content).context("Failed to read secrets from stdin")?;
+        content
+    } else {
+        fs::read_to_string(source).context(format!("Failed to read {source}"))?
+    };
+    let format = matches.get_one::<ImportFormat>("format").copied()
+        .unwrap_or_else(|| guess_format(source, &content));
+    let secrets = parse_secrets(&content, format)
+        .context(format!("Failed to parse {source} as {format:?}"))?;
+    if secrets.is_empty() {
+        bail!("No secrets found in {source}");
+    }
+
+    let vault = vault_ids.passwords()?;
+    let _lock = lock_vault_file(vault_file_path)?;
+    let mut vault_file = if Path::new(vault_file_path).exists() {
+        decrypt_vault_file(vault_file_path, &vault)?
+    } else {
+        VaultDocument::parse("---\n".to_string())?
+    };
+
+    let dry_run = matches.get_flag("dry-run");
+    let overwrite = matches.get_flag("overwrite");
+    let mut changes = Vec::new();
+    for (name, value) in secrets {
+        let path = KeyPath::parse(&name);
+        let change = match vault_file.get(&path) {
+            None => "add",
+            Some(existing) if *existing == value => "same",
+            Some(_) => "overwrite",
+        };
+        println!("{change:9}  {name}");
+        changes.push((path, value, change));
+    }
+
+    let conflicts = changes.iter().filter(|(_, _, change)| *change == "overwrite").count();
+    if dry_run {
+        return Ok(());
+    }
+    if conflicts > 0 && !overwrite && source == "-" {
+        bail!("{conflicts} secrets exist already in {vault_file_path}, pass --overwrite to replace them");
+    }
+
+    let mut imported = Vec::new();
+    let mut resolution = if overwrite { Some(Resolution::OverwriteAll) } else { None };
+    for (path, value, change) in changes {
+        if change == "same" {
+            continue;
+        }
+        if change == "overwrite" {
+            let choice = match resolution {
+                Some(choice) => choice,
+                None => select(&format!("`{path}` exists already in {vault_file_path}"), Resolution::value_variants().to_vec())
+                    .context(format!("{conflicts} secrets exist already, pass --overwrite to replace them"))?,
+            };
+            if matches!(choice, Resolution::OverwriteAll | Resolution::KeepAll) {
+                resolution = Some(choice);
+            }
+            if matches!(choice, Resolution::Keep | Resolution::KeepAll) {
+                continue;
+            }
+        }
+        vault_file.set(&path, value)?;
+        imported.push(path);
+    }
+
+    if imported.is_empty() {
+        println!("Nothing to import, {vault_file_path} was not changed");
+        return Ok(());
+    }
+    write_vault_file(vault_file_path, &vault_file, &vault, config.vault_backups)?;
+    println!("Imported {} secrets into {vault_file_path}", imported.len());
+
+    let expires_in = matches.get_one::<Duration>("expires-in").copied();
+    update_metadata(vault_file_path, config, expires_in.is_some(), |metadata| {
+        imported.iter().try_for_each(|path| metadata.updated(&path.to_string(), expires_in))
+    })
+}
+
+fn guess_format(source: &str, content: &str) -> ImportFormat {
+    let file_name = Path::new(source).file_name().unwrap_or_default().to_string_lossy();
+    if file_name.starts_with(".env") || file_name.ends_with(".env") {
+        return ImportFormat::Env;
+    }
+    match Path::new(source).extension().and_then(|extension| extension.to_str()) {
+        Some("json") => ImportFormat::Json,
+        Some("yml") | Some("yaml") => ImportFormat::Yaml,
+        _ => match serde_yaml::from_str::<Value>(content) {
+            Ok(Value::Mapping(_)) if content.trim_start().starts_with('{') => ImportFormat::Json,
+            Ok(Value::Mapping(_)) => ImportFormat::Yaml,
+            _ => ImportFormat::Env,
+        },
+    }
+}
+
+/// The secrets with their names normalized like single secrets, in the order of the source.
+fn parse_secrets(content: &str, format: ImportFormat) -> Result<Vec<(String, Value)>> {
+    let secrets: Mapping = match format {
+        ImportFormat::Env => parse_env(content)?,
+        ImportFormat::Json => serde_json::from_str(content)?,
+        ImportFormat::Yaml => match serde_yaml::from_str(content)? {
+            Value::Null => Mapping::new(),
+            Value::Mapping(mapping) => mapping,
+            _ => bail!("Expected a mapping of secret names to values"),
+        },
+    };
+
+    secrets.into_iter()
+        .map(|(name, value)| match name {
+            Value::String(name) => Ok((normalize_secret_name(&name), value)),
+            name => bail!("Unsupported secret name {name:?}"),
+        })
+        .collect()
+}
+
+/// Parses `KEY=value` lines with optional `export`, quotes and comments.
+/// Names are lower cased, so `DB_PASSWORD` and the exported `VAULT_DB_PASSWORD` both import as `vault_db_password`.
+fn parse_env(content: &str) -> Result<Mapping> {
+    let mut secrets = Mapping::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line.split_once('=')
+            .with_context(|| format!("Expected `KEY=value` on line {}", index + 1))?;
+        let value = value.trim();
+
+        let value = if let Some(quoted) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            unescape(quoted)
+        } else if let Some(quoted) = value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')) {
+            quoted.to_string()
+        } else {
+            value.split(" #").next().unwrap().trim_end().to_string()
+        };
+        secrets.insert(Value::String(name.trim().to_lowercase()), Value::String(value));
+    }
+    Ok(secrets)
+}
+
+/// Resolves `\n`, `\"` and `\\` in double quoted values.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => { unescaped.push('\n'); chars.next(); }
+            ('\\', Some(escaped @ ('"' | '\\'))) => { unescaped.push(escaped); chars.next(); }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_yaml::Value;
+
+use crate::vault::storage::write_atomically;
+use crate::vault::vault_id::VaultPasswords;
+use crate::vault::yaml_edit::set_entry;
+
+const VAULT_TAG: &str = "!vault";
+const INLINE_INDENT: &str = "          ";
+
+/// Inserts or updates `name: !vault |` in a plain YAML file, like `ansible-vault encrypt_string` output.
+/// An existing inline value keeps its vault ID.
+pub fn add_inline_secret_to_file(name: &str, secret: &str, vars_file_path: &str, vault: &VaultPasswords) -> Result<()> {
+    let content = if Path::new(vars_file_path).exists() {
+        fs::read_to_string(vars_file_path).context(format!("Failed to read {vars_file_path}"))?
+    } else {
+        "---\n".to_string()
+    };
+    if content.starts_with("$ANSIBLE_VAULT;") {
+        bail!("{vars_file_path} is an encrypted vault file, inline `!vault` values go into plain YAML files");
+    }
+
+    let existing = inline_ciphertext(&content, name, vars_file_path)?;
+    let encrypted = vault.encrypt_like(existing.as_deref().map(str::as_bytes), secret.as_bytes())?;
+
+    let mut entry = format!("{name}: {VAULT_TAG} |\n");
+    for line in encrypted.lines() {
+        entry.push_str(INLINE_INDENT);
+        entry.push_str(line);
+        entry.push('\n');
+    }
+
+    write_atomically(Path::new(vars_file_path), set_entry(&content, name, &entry).as_bytes())
+}
+
+/// Reads `name` from a plain YAML file, decrypting it if it is an inline `!vault` value.
+pub fn read_inline_secret(name: &str, vars_file_path: &str, vault: &VaultPasswords) -> Result<Option<String>> {
+    let content = fs::read_to_string(vars_file_path).context(format!("Failed to read {vars_file_path}"))?;
+    let vars: Value = serde_yaml::from_str(&content).context(format!("Failed to parse {vars_file_path}"))?;
+
+    match vars.get(name) {
+        None => Ok(None),
+        Some(Value::Tagged(tagged)) if tagged.tag == VAULT_TAG => {
+            let ciphertext = tagged.value.as_str()
+                .with_context(|| format!("`{name}` in {vars_file_path} is not an encrypted string"))?;
+            let decrypted = vault.decrypt(ciphertext.as_bytes())
+                .context(format!("Failed to decrypt `{name}` in {vars_file_path}"))?;
+            Ok(Some(String::from_utf8(decrypted).context("UTF-8 content expected")?))
+        }
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(value) => Ok(Some(serde_yaml::to_string(value)?.trim_end().to_string())),
+    }
+}
+
+fn inline_ciphertext(content: &str, name: &str, vars_file_path: &str) -> Result<Option<String>> {
+    let vars: Value = serde_yaml::from_str(content).context(format!("Failed to parse {vars_file_path}"))?;
+    match vars.get(name) {
+        Some(Value::Tagged(tagged)) if tagged.tag == VAULT_TAG => Ok(tagged.value.as_str().map(str::to_string)),
+        Some(_) => bail!("`{name}` in {vars_file_path} is a plain value, refusing to overwrite it"),
+        None => Ok(None),
+    }
+}
+
+pub fn is_plain_yaml(path: &str) -> bool {
+    fs::read(path)
+        .map(|content| !content.starts_with(b"$ANSIBLE_VAULT;"))
+        .unwrap_or(false)
+}
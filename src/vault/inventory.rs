@@ -0,0 +1,262 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_yaml::Value;
+
+use crate::config::Config;
+use crate::core::{Choice, select};
+use crate::vault::ansible_cfg::{AnsibleConfig, expand_home};
+
+const ALL_GROUP: &str = "all";
+const VAULT_FILE_NAMES: [&str; 2] = ["vault.yml", "vault.yaml"];
+const VARS_DIRS: [&str; 2] = ["group_vars", "host_vars"];
+const IGNORED_EXTENSIONS: [&str; 5] = ["retry", "pyc", "cfg", "md", "txt"];
+/// Top-level key of inventory plugin configs like `aws_ec2.yml`, which list no groups themselves.
+const PLUGIN_KEY: &str = "plugin";
+
+/// A group or host of the inventory, its vault file is `group_vars/<group>/vault.yml` or `host_vars/<host>/vault.yml`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Group(String),
+    Host(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Group(group) => write!(f, "group {group}"),
+            Target::Host(host) => write!(f, "host {host}"),
+        }
+    }
+}
+
+impl Target {
+    /// The existing vault file of the target, or the conventional path for a new one with its directory created.
+    pub fn vault_file_path(&self, inventory_dir: &Path) -> Result<PathBuf> {
+        let (vars_dir, name) = match self {
+            Target::Group(group) => (VARS_DIRS[0], group),
+            Target::Host(host) => (VARS_DIRS[1], host),
+        };
+        let dir = inventory_dir.join(vars_dir).join(name);
+        if let Some(existing) = VAULT_FILE_NAMES.iter().map(|file_name| dir.join(file_name)).find(|path| path.is_file()) {
+            return Ok(existing);
+        }
+        fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+        Ok(dir.join(VAULT_FILE_NAMES[0]))
+    }
+}
+
+/// The groups and hosts of the INI and YAML inventory files, dynamic inventory scripts and plugins are not run.
+pub struct Inventory {
+    /// `group_vars` and `host_vars` are next to the (first) inventory.
+    pub(crate) dir: PathBuf,
+    groups: BTreeSet<String>,
+    hosts: BTreeSet<String>,
+}
+
+impl Inventory {
+    /// Loads the inventory from `inventory` in the tdtk config, `$ANSIBLE_INVENTORY` or `ansible.cfg`.
+    pub fn load(config: &Config) -> Result<Option<Self>> {
+        let paths = match inventory_paths(config)? {
+            Some(paths) => paths,
+            None => return Ok(None),
+        };
+
+        let mut inventory = Inventory {
+            dir: if paths[0].is_dir() { paths[0].clone() } else { parent_dir(&paths[0]) },
+            groups: BTreeSet::from([ALL_GROUP.to_string()]),
+            hosts: BTreeSet::new(),
+        };
+        for path in &paths {
+            inventory.read(path)?;
+        }
+        Ok(Some(inventory))
+    }
+
+    pub fn targets(&self) -> Vec<Target> {
+        self.groups.iter().cloned().map(Target::Group)
+            .chain(self.hosts.iter().cloned().map(Target::Host))
+            .collect()
+    }
+
+    pub fn contains(&self, target: &Target) -> bool {
+        match target {
+            Target::Group(group) => self.groups.contains(group),
+            Target::Host(host) => self.hosts.contains(host),
+        }
+    }
+
+    fn read(&mut self, path: &Path) -> Result<()> {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path).context(format!("Failed to read {}", path.display()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                if !is_ignored(&entry) {
+                    self.read(&entry)?;
+                }
+            }
+            return Ok(());
+        }
+        if is_executable(path) {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(path).context(format!("Failed to read inventory {}", path.display()))?;
+        if is_yaml(path) {
+            let groups: Value = serde_yaml::from_str(&content)
+                .context(format!("Failed to parse inventory {}", path.display()))?;
+            if groups.get(PLUGIN_KEY).is_none() {
+                self.read_yaml_groups(&groups);
+            }
+        } else {
+            self.read_ini(&content);
+        }
+        Ok(())
+    }
+
+    /// `[group]` sections list hosts, `[group:children]` sections list groups.
+    fn read_ini(&mut self, content: &str) {
+        let mut section: Option<(String, &str)> = None;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let (group, kind) = header.split_once(':').unwrap_or((header, "hosts"));
+                self.groups.insert(group.to_string());
+                section = Some((group.to_string(), kind));
+                continue;
+            }
+
+            let name = line.split_whitespace().next().unwrap_or_default();
+            match section {
+                Some((_, "vars")) => {}
+                Some((_, "children")) => {
+                    self.groups.insert(name.to_string());
+                }
+                _ => self.hosts.extend(expand_host_pattern(name)),
+            }
+        }
+    }
+
+    /// `group: {hosts: {...}, children: {group: {...}}}`
+    fn read_yaml_groups(&mut self, groups: &Value) {
+        let Some(groups) = groups.as_mapping() else { return };
+        for (group, entry) in groups {
+            let Some(group) = group.as_str() else { continue };
+            self.groups.insert(group.to_string());
+            if let Some(hosts) = entry.get("hosts").and_then(Value::as_mapping) {
+                for host in hosts.keys().filter_map(Value::as_str) {
+                    self.hosts.extend(expand_host_pattern(host));
+                }
+            }
+            if let Some(children) = entry.get("children") {
+                self.read_yaml_groups(children);
+            }
+        }
+    }
+}
+
+/// Lets the user pick a group or host of the inventory, `None` if they want to enter a path instead.
+pub fn prompt_target(inventory: &Inventory) -> Result<Option<Target>> {
+    let targets = inventory.targets();
+    let mut choices: Vec<Choice<usize>> = targets.iter().enumerate()
+        .map(|(index, target)| Choice { choice: index, prompt: target.to_string() })
+        .collect();
+    choices.push(Choice { choice: targets.len(), prompt: "Enter the path of a vault file".to_string() });
+
+    let index = select("Which group or host is the secret for?", choices)?.choice;
+    Ok(targets.get(index).cloned())
+}
+
+fn inventory_paths(config: &Config) -> Result<Option<Vec<PathBuf>>> {
+    let (inventory, ansible_config) = match (&config.inventory, std::env::var("ANSIBLE_INVENTORY")) {
+        (Some(inventory), _) => (inventory.clone(), None),
+        (None, Ok(inventory)) => (inventory, None),
+        (None, Err(_)) => match AnsibleConfig::load()? {
+            Some(ansible_config) => match ansible_config.get("inventory") {
+                Some(inventory) => (inventory.to_string(), Some(ansible_config)),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        },
+    };
+
+    let paths: Vec<PathBuf> = inventory.split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| match &ansible_config {
+            Some(ansible_config) => ansible_config.resolve(path),
+            None => expand_home(path),
+        })
+        .filter(|path| path.exists())
+        .collect();
+    Ok(if paths.is_empty() { None } else { Some(paths) })
+}
+
+/// Expands ansible host ranges like `web[01:03].example.com` or `db-[a:c]`.
+fn expand_host_pattern(pattern: &str) -> Vec<String> {
+    let range = pattern.find('[')
+        .and_then(|start| Some((start, start + pattern[start..].find(']')?)));
+    let Some((start, end)) = range else { return vec![pattern.to_string()] };
+    let Some((from, to)) = pattern[start + 1..end].split_once(':') else { return vec![pattern.to_string()] };
+    let to = to.split(':').next().unwrap_or(to);
+    let (prefix, suffix) = (&pattern[..start], &pattern[end + 1..]);
+
+    let values: Vec<String> = match (from.parse::<usize>(), to.parse::<usize>()) {
+        (Ok(first), Ok(last)) => (first..=last).map(|n| format!("{n:0width$}", width = from.len())).collect(),
+        _ => match (single_char(from), single_char(to)) {
+            (Some(first), Some(last)) => (first..=last).map(String::from).collect(),
+            _ => return vec![pattern.to_string()],
+        },
+    };
+    values.iter()
+        .flat_map(|value| expand_host_pattern(&format!("{prefix}{value}{suffix}")))
+        .collect()
+}
+
+fn single_char(value: &str) -> Option<char> {
+    let mut chars = value.chars();
+    chars.next().filter(|_| chars.next().is_none())
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(path.extension().and_then(|extension| extension.to_str()), Some("yml" | "yaml"))
+}
+
+/// Skips vars directories, hidden files and dynamic inventory scripts, like ansible's inventory directory loading.
+fn is_ignored(path: &Path) -> bool {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    file_name.starts_with('.') || file_name.ends_with('~') || is_executable(path)
+        || VARS_DIRS.contains(&file_name.as_ref())
+        || IGNORED_EXTENSIONS.contains(&extension)
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// The vault file of a group or host given on the command line.
+pub fn target_vault_file(target: &Target, config: &Config) -> Result<String> {
+    let inventory = Inventory::load(config)?
+        .context("No inventory found, set `inventory` in ansible.cfg or the tdtk config")?;
+    if !inventory.contains(target) {
+        bail!("There is no {target} in the inventory");
+    }
+    Ok(target.vault_file_path(&inventory.dir)?.to_string_lossy().to_string())
+}
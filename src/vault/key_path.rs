@@ -0,0 +1,126 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::vault::{normalize_secret_name, normalize_variable_name};
+
+/// A dotted path to a value in a vault file, e.g. `vault_db.primary.password`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPath(Vec<String>);
+
+impl KeyPath {
+    pub fn parse(key: &str) -> Self {
+        KeyPath(key.split('.').map(str::to_string).collect())
+    }
+
+    pub fn from_segments(segments: Vec<String>) -> Self {
+        KeyPath(segments)
+    }
+
+    /// Applies the secret name normalization to every segment,
+    /// only the top-level name gets the `vault_` prefix.
+    pub fn normalized(key: &str) -> Self {
+        KeyPath(key.split('.')
+            .enumerate()
+            .map(|(index, segment)| if index == 0 {
+                normalize_secret_name(segment)
+            } else {
+                normalize_variable_name(segment)
+            })
+            .collect())
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn parent(&self) -> &[String] {
+        &self.0[..self.0.len() - 1]
+    }
+
+    /// This path followed by all of its parents, the top-level name last.
+    pub fn ancestors(&self) -> Vec<KeyPath> {
+        (1..=self.0.len()).rev()
+            .map(|length| KeyPath(self.0[..length].to_vec()))
+            .collect()
+    }
+
+    pub fn leaf(&self) -> &str {
+        self.0.last().unwrap()
+    }
+
+    /// The sibling of this path, e.g. `a.b_previous` for `a.b` and `_previous`.
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        let mut segments = self.0.clone();
+        segments.last_mut().unwrap().push_str(suffix);
+        KeyPath(segments)
+    }
+
+    pub fn get<'a>(&self, document: &'a Mapping) -> Option<&'a Value> {
+        let (leaf, parents) = self.0.split_last().unwrap();
+        let mut mapping = document;
+        for segment in parents {
+            mapping = mapping.get(segment.as_str())?.as_mapping()?;
+        }
+        mapping.get(leaf.as_str())
+    }
+
+    /// Sets the value, creating missing parent mappings, and returns the previous value.
+    pub fn set(&self, document: &mut Mapping, value: Value) -> Result<Option<Value>> {
+        let (leaf, parents) = self.0.split_last().unwrap();
+        let mut mapping = document;
+        for (index, segment) in parents.iter().enumerate() {
+            let child = mapping.entry(Value::String(segment.clone()))
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            mapping = match child {
+                Value::Mapping(child) => child,
+                _ => bail!("`{}` is not a mapping", self.0[..=index].join(".")),
+            };
+        }
+        Ok(mapping.insert(Value::String(leaf.clone()), value))
+    }
+
+    pub fn remove(&self, document: &mut Mapping) -> Option<Value> {
+        let (leaf, parents) = self.0.split_last().unwrap();
+        let mut mapping = document;
+        for segment in parents {
+            mapping = mapping.get_mut(segment.as_str())?.as_mapping_mut()?;
+        }
+        mapping.remove(leaf.as_str())
+    }
+
+    /// All leaf values with their paths, mappings are descended into.
+    pub fn leaves(document: &Mapping) -> Vec<(KeyPath, &Value)> {
+        let mut leaves = Vec::new();
+        collect_leaves(document, &mut Vec::new(), &mut leaves);
+        leaves
+    }
+}
+
+fn collect_leaves<'a>(mapping: &'a Mapping, path: &mut Vec<String>, leaves: &mut Vec<(KeyPath, &'a Value)>) {
+    for (key, value) in mapping {
+        path.push(scalar_to_string(key));
+        match value {
+            Value::Mapping(child) if !child.is_empty() => collect_leaves(child, path, leaves),
+            _ => leaves.push((KeyPath(path.clone()), value)),
+        }
+        path.pop();
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join("."))
+    }
+}
+
+/// Strings as they are, everything else as YAML.
+pub fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => serde_yaml::to_string(value)
+            .map(|yaml| yaml.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use chrono::{Datelike, Duration, Utc};
+use clap::ValueEnum;
+use derive_more::Display;
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rcgen::{CertificateParams, date_time_ymd, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P256_SHA256};
+use ssh_key::{Algorithm, LineEnding, PrivateKey};
+
+use crate::core::{select, text};
+
+/// Typed key material offered next to plain random secrets.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeyType {
+    #[display(fmt = "SSH key pair (ed25519)")]
+    SshEd25519,
+    #[display(fmt = "SSH key pair (RSA 4096)")]
+    SshRsa,
+    #[display(fmt = "Self-signed TLS certificate and private key")]
+    Tls,
+    #[display(fmt = "JWT signing key (HMAC)")]
+    JwtHmac,
+    #[display(fmt = "JWT signing key pair (ES256)")]
+    JwtEs256,
+}
+
+/// What the Generate action produces.
+#[derive(Display, Clone, Copy)]
+pub enum Generator {
+    #[display(fmt = "A random secret")]
+    Random,
+    Key(KeyType),
+}
+
+pub fn prompt_generator() -> Result<Generator> {
+    let generators = std::iter::once(Generator::Random)
+        .chain(KeyType::value_variants().iter().map(|key_type| Generator::Key(*key_type)))
+        .collect();
+    select("What do you want to generate?", generators)
+}
+
+/// The private half goes into the vault, the public half is handed out in plain text.
+pub struct KeyMaterial {
+    pub(crate) private: String,
+    pub(crate) public: Option<PublicHalf>,
+}
+
+pub struct PublicHalf {
+    pub(crate) extension: &'static str,
+    pub(crate) content: String,
+}
+
+const TLS_VALIDITY_DAYS: i64 = 365;
+
+impl KeyType {
+    pub fn generate(&self, secret_name: &str, subject_alt_names: &[String]) -> Result<KeyMaterial> {
+        match self {
+            KeyType::SshEd25519 => generate_ssh_key(Algorithm::Ed25519, secret_name),
+            KeyType::SshRsa => generate_ssh_key(Algorithm::Rsa { hash: None }, secret_name),
+            KeyType::Tls => generate_tls_certificate(&prompt_subject_alt_names(subject_alt_names)?),
+            KeyType::JwtHmac => {
+                let mut bytes = [0u8; 64];
+                ChaCha20Rng::from_entropy().fill_bytes(&mut bytes);
+                Ok(KeyMaterial {
+                    private: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+                    public: None,
+                })
+            }
+            KeyType::JwtEs256 => {
+                let key_pair = KeyPair::generate(&PKCS_ECDSA_P256_SHA256)
+                    .context("Failed to generate ES256 key pair")?;
+                Ok(KeyMaterial {
+                    private: key_pair.serialize_pem(),
+                    public: Some(PublicHalf { extension: "pub.pem", content: key_pair.public_key_pem() }),
+                })
+            }
+        }
+    }
+}
+
+fn generate_ssh_key(algorithm: Algorithm, comment: &str) -> Result<KeyMaterial> {
+    let mut private_key = PrivateKey::random(&mut ChaCha20Rng::from_entropy(), algorithm)
+        .context("Failed to generate SSH key")?;
+    private_key.set_comment(comment);
+
+    Ok(KeyMaterial {
+        private: private_key.to_openssh(LineEnding::LF)
+            .context("Failed to encode SSH private key")?
+            .to_string(),
+        public: Some(PublicHalf {
+            extension: "pub",
+            content: private_key.public_key().to_openssh()
+                .context("Failed to encode SSH public key")? + "\n",
+        }),
+    })
+}
+
+fn generate_tls_certificate(subject_alt_names: &[String]) -> Result<KeyMaterial> {
+    let mut params = CertificateParams::new(subject_alt_names);
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, subject_alt_names[0].as_str());
+    params.distinguished_name = distinguished_name;
+
+    let (now, expires) = (Utc::now(), Utc::now() + Duration::days(TLS_VALIDITY_DAYS));
+    params.not_before = date_time_ymd(now.year(), now.month() as u8, now.day() as u8);
+    params.not_after = date_time_ymd(expires.year(), expires.month() as u8, expires.day() as u8);
+
+    let certificate = rcgen::Certificate::from_params(params)
+        .context("Failed to generate TLS certificate")?;
+
+    Ok(KeyMaterial {
+        private: certificate.serialize_private_key_pem(),
+        public: Some(PublicHalf {
+            extension: "crt",
+            content: certificate.serialize_pem().context("Failed to encode TLS certificate")?,
+        }),
+    })
+}
+
+fn prompt_subject_alt_names(subject_alt_names: &[String]) -> Result<Vec<String>> {
+    if !subject_alt_names.is_empty() {
+        return Ok(subject_alt_names.to_vec());
+    }
+
+    let names: Vec<String> = text("Which host names should the certificate cover? (comma separated)")?
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        bail!("The certificate needs at least one host name");
+    }
+    Ok(names)
+}
+
+/// Writes the public half next to the vault file, named after the secret without the `vault_` prefix.
+pub fn write_public_half(public: &PublicHalf, secret_name: &str, vault_file_path: &str) -> Result<PathBuf> {
+    let name = secret_name.strip_prefix("vault_").unwrap_or(secret_name);
+    let path = Path::new(vault_file_path)
+        .with_file_name(format!("{name}.{}", public.extension));
+    fs::write(&path, &public.content)
+        .context(format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use clap::{arg, ArgAction, ArgMatches, Command};
+use serde_yaml::{Mapping, Value};
+
+use crate::config::Config;
+use crate::core::{capture_command, parse_duration};
+use crate::vault::{EXPIRES_FORMAT, find_vault_files};
+use crate::vault::document::VaultDocument;
+use crate::vault::key_path::KeyPath;
+use crate::vault::storage::{lock_vault_file, VaultLock, write_atomically};
+
+/// All metadata is kept under one variable, ansible loads the sidecar like any other vars file.
+const METADATA_ROOT: &str = "tdtk_secret_metadata";
+const METADATA_HEADER: &str = "# Maintained by tdtk: when the secrets of the vault file next to it were created, rotated and expire, without their values\n";
+const CREATED: &str = "created";
+const CREATED_BY: &str = "created_by";
+const ROTATED: &str = "rotated";
+const ROTATED_BY: &str = "rotated_by";
+const EXPIRES: &str = "expires";
+/// When the value a rotation replaced may be dropped, kept out of the vault so ansible doesn't see it as a variable.
+const PREVIOUS_EXPIRES: &str = "previous_expires";
+
+const EXIT_EXPIRING: i32 = 1;
+const EXIT_EXPIRED: i32 = 2;
+
+/// The plain `<name>.meta.yml` sidecar of a vault file, locked while it is open.
+pub struct SecretMetadata {
+    path: PathBuf,
+    document: VaultDocument,
+    changed: bool,
+    _lock: VaultLock,
+}
+
+impl SecretMetadata {
+    /// Opens the sidecar if `secret_metadata` is enabled, it exists already or `create` is set.
+    pub fn open(vault_file_path: &str, config: &Config, create: bool) -> Result<Option<Self>> {
+        let path = metadata_path(Path::new(vault_file_path));
+        if !config.secret_metadata && !create && !path.exists() {
+            return Ok(None);
+        }
+
+        let lock = lock_vault_file(&path.to_string_lossy())?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => format!("{METADATA_HEADER}---\n"),
+        };
+        let document = VaultDocument::parse(content)
+            .context(format!("Failed to parse {}", path.display()))?;
+        Ok(Some(SecretMetadata { path, document, changed: false, _lock: lock }))
+    }
+
+    /// Records a new value of the secret, its expiry moves by `expires_in` or by its previous lifetime.
+    pub fn updated(&mut self, name: &str, expires_in: Option<Duration>) -> Result<()> {
+        let previous = self.document.get(&entry_path(name, "")).is_some();
+        self.record(name, previous, expires_in)
+    }
+
+    /// Records a rotation, the previous value is kept until `previous_expires`.
+    pub fn rotated(&mut self, name: &str, expires_in: Option<Duration>, previous_expires: DateTime<Utc>) -> Result<()> {
+        self.record(name, true, expires_in)?;
+        self.set(name, PREVIOUS_EXPIRES, format_timestamp(previous_expires))
+    }
+
+    /// The previous value of the secret was dropped from the vault.
+    pub fn previous_dropped(&mut self, name: &str) -> Result<()> {
+        if self.document.remove(&entry_path(name, PREVIOUS_EXPIRES))?.is_some() {
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    /// The secrets whose previous value expired by `now`.
+    pub fn expired_previous(&self, now: DateTime<Utc>) -> Vec<String> {
+        let Some(secrets) = self.document.values().get(METADATA_ROOT).and_then(Value::as_mapping) else { return Vec::new() };
+        secrets.keys()
+            .filter_map(Value::as_str)
+            .filter(|name| self.timestamp(name, PREVIOUS_EXPIRES).map(|expires| expires <= now).unwrap_or(false))
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn record(&mut self, name: &str, previous: bool, expires_in: Option<Duration>) -> Result<()> {
+        let now = Utc::now();
+        let user = current_user();
+        let lifetime = expires_in.or_else(|| {
+            let since = self.timestamp(name, ROTATED).or_else(|| self.timestamp(name, CREATED))?;
+            Some(self.timestamp(name, EXPIRES)? - since)
+        });
+        if previous {
+            self.set(name, ROTATED, format_timestamp(now))?;
+            self.set(name, ROTATED_BY, user)?;
+        } else {
+            self.set(name, CREATED, format_timestamp(now))?;
+            self.set(name, CREATED_BY, user)?;
+        }
+        if let Some(lifetime) = lifetime {
+            self.set(name, EXPIRES, format_timestamp(now + lifetime))?;
+        }
+        Ok(())
+    }
+
+    pub fn removed(&mut self, name: &str) -> Result<()> {
+        if self.document.remove(&entry_path(name, ""))?.is_some() {
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn renamed(&mut self, old_name: &str, new_name: &str) -> Result<()> {
+        if self.document.get(&entry_path(old_name, "")).is_some() {
+            self.document.rename(&entry_path(old_name, ""), &entry_path(new_name, ""))?;
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn save(self) -> Result<()> {
+        if self.changed {
+            write_atomically(&self.path, self.document.content().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, field: &str, value: String) -> Result<()> {
+        self.document.set(&entry_path(name, field), Value::String(value))?;
+        self.changed = true;
+        Ok(())
+    }
+
+    fn timestamp(&self, name: &str, field: &str) -> Option<DateTime<Utc>> {
+        parse_timestamp(self.document.get(&entry_path(name, field))?)
+    }
+}
+
+/// Applies `change` to the metadata of the vault file, if it has metadata or `create` is set.
+pub fn update_metadata(
+    vault_file_path: &str,
+    config: &Config,
+    create: bool,
+    change: impl FnOnce(&mut SecretMetadata) -> Result<()>,
+) -> Result<()> {
+    match SecretMetadata::open(vault_file_path, config, create)? {
+        Some(mut metadata) => {
+            change(&mut metadata)?;
+            metadata.save()
+        }
+        None => Ok(()),
+    }
+}
+
+/// Secret names are single keys, even dotted ones, so the metadata of nested secrets stays flat.
+/// An empty `field` is the entry of the secret itself.
+fn entry_path(name: &str, field: &str) -> KeyPath {
+    let mut segments = vec![METADATA_ROOT.to_string(), name.to_string()];
+    if !field.is_empty() {
+        segments.push(field.to_string());
+    }
+    KeyPath::from_segments(segments)
+}
+
+/// `vault.yml` has its metadata in `vault.meta.yml`.
+fn metadata_path(vault_file_path: &Path) -> PathBuf {
+    let stem = vault_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    vault_file_path.with_file_name(format!("{stem}.meta.yml"))
+}
+
+fn current_user() -> String {
+    capture_command("git", &["config", "user.name"], None)
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("USER").ok().filter(|user| !user.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format(EXPIRES_FORMAT).to_string()
+}
+
+fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.as_str()?, EXPIRES_FORMAT).ok()
+        .map(|timestamp| DateTime::<Utc>::from_utc(timestamp, Utc))
+}
+
+pub fn expiring_command() -> Command {
+    Command::new("expiring")
+        .about("Report secrets that expire soon, from the metadata next to the vault files")
+        .arg(arg!([PATH] "Vault files, globs or directories to check").action(ArgAction::Append).default_value("."))
+        .arg(arg!(--within <DURATION> "Report secrets expiring within this time (e.g. 30d)")
+            .value_parser(parse_duration)
+            .default_value("30d"))
+        .arg(arg!(--"expired-only" "Only report secrets that expired already"))
+}
+
+/// Exits with 1 if secrets expire within the given time and 2 if some expired already.
+pub fn handle_expiring_command(matches: &ArgMatches) -> Result<()> {
+    let within = *matches.get_one::<Duration>("within").unwrap();
+    let expired_only = matches.get_flag("expired-only");
+    let now = Utc::now();
+
+    let mut vault_files = Vec::new();
+    for path in matches.get_many::<String>("PATH").unwrap() {
+        vault_files.extend(find_vault_files(path)?);
+    }
+
+    let mut expiring = Vec::new();
+    for vault_file in &vault_files {
+        let path = metadata_path(vault_file);
+        let Ok(content) = fs::read_to_string(&path) else { continue };
+        let metadata: Value = serde_yaml::from_str(&content)
+            .context(format!("Failed to parse {}", path.display()))?;
+        let secrets = metadata.get(METADATA_ROOT).and_then(Value::as_mapping).cloned().unwrap_or_else(Mapping::new);
+
+        for (name, entry) in &secrets {
+            let Some(expires) = entry.get(EXPIRES).and_then(parse_timestamp) else { continue };
+            if expires <= now || (!expired_only && expires <= now + within) {
+                expiring.push((vault_file.display().to_string(), name.as_str().unwrap_or_default().to_string(), expires));
+            }
+        }
+    }
+
+    if expiring.is_empty() {
+        if expired_only {
+            println!("No secrets have expired");
+        } else {
+            println!("No secrets expire before {}", format_timestamp(now + within));
+        }
+        return Ok(());
+    }
+
+    expiring.sort_by_key(|(_, _, expires)| *expires);
+    let file_width = expiring.iter().map(|(file, _, _)| file.len()).max().unwrap_or(0);
+    let name_width = expiring.iter().map(|(_, name, _)| name.len()).max().unwrap_or(0);
+    for (file, name, expires) in &expiring {
+        let status = if *expires <= now { "expired" } else { "expiring" };
+        println!("{status:8}  {file:file_width$}  {name:name_width$}  {}", format_timestamp(*expires));
+    }
+
+    if expiring.iter().any(|(_, _, expires)| *expires <= now) {
+        std::process::exit(EXIT_EXPIRED);
+    }
+    std::process::exit(EXIT_EXPIRING)
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use glob::Pattern;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::core::{Choice, select};
+
+/// The BIP39 english wordlist, 2048 words or 11 bits of entropy per word.
+const WORDLIST: &str = include_str!("wordlist.txt");
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+/// How a secret is generated, configurable in `.tdtk.toml`:
+///
+/// ```toml
+/// [secret_policies]
+/// hex32 = { alphabet = "hex", length = 32 }
+/// pin = { alphabet = "custom", characters = "0123456789", length = 8 }
+/// passphrase = { words = 6, separator = "-" }
+/// bytes64 = { bytes = 64 }
+///
+/// [secret_policy_patterns]
+/// "*_api_key" = "hex32"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SecretPolicy {
+    Passphrase {
+        words: usize,
+        #[serde(default = "default_separator")]
+        separator: String,
+    },
+    Bytes {
+        bytes: usize,
+    },
+    Random {
+        alphabet: Alphabet,
+        length: usize,
+        characters: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Alphabet {
+    Alphanumeric,
+    Hex,
+    Base64,
+    Base64url,
+    Symbols,
+    Custom,
+}
+
+fn default_separator() -> String {
+    "-".to_string()
+}
+
+impl fmt::Display for SecretPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretPolicy::Passphrase { words, separator } =>
+                write!(f, "passphrase of {words} words separated by `{separator}`"),
+            SecretPolicy::Bytes { bytes } =>
+                write!(f, "{bytes} random bytes, base64 encoded"),
+            SecretPolicy::Random { alphabet, length, .. } =>
+                write!(f, "{length} {} characters", format!("{alphabet:?}").to_lowercase()),
+        }
+    }
+}
+
+impl SecretPolicy {
+    pub fn generate(&self) -> Result<String> {
+        let mut rng = ChaCha20Rng::from_entropy();
+        match self {
+            SecretPolicy::Passphrase { words, separator } => {
+                let wordlist: Vec<&str> = WORDLIST.lines().collect();
+                let words: Vec<&str> = (0..*words)
+                    .map(|_| *wordlist.choose(&mut rng).unwrap())
+                    .collect();
+                Ok(words.join(separator))
+            }
+            SecretPolicy::Bytes { bytes } => {
+                let mut buffer = vec![0u8; *bytes];
+                rng.fill_bytes(&mut buffer);
+                Ok(base64::engine::general_purpose::STANDARD.encode(buffer))
+            }
+            SecretPolicy::Random { alphabet, length, characters } => {
+                let characters: Vec<char> = alphabet.characters(characters.as_deref())?.chars().collect();
+                Ok((0..*length)
+                    .map(|_| characters[rng.gen_range(0..characters.len())])
+                    .collect())
+            }
+        }
+    }
+}
+
+impl Alphabet {
+    fn characters(&self, custom: Option<&str>) -> Result<String> {
+        let alphanumeric = ('A'..='Z').chain('a'..='z').chain('0'..='9');
+        let characters: String = match self {
+            Alphabet::Alphanumeric => alphanumeric.collect(),
+            Alphabet::Hex => "0123456789abcdef".to_string(),
+            Alphabet::Base64 => alphanumeric.chain("+/".chars()).collect(),
+            Alphabet::Base64url => alphanumeric.chain("-_".chars()).collect(),
+            Alphabet::Symbols => alphanumeric.chain(SYMBOLS.chars()).collect(),
+            Alphabet::Custom => custom
+                .filter(|characters| !characters.is_empty())
+                .context("The custom alphabet needs non-empty `characters`")?
+                .to_string(),
+        };
+        Ok(characters)
+    }
+}
+
+/// The policy used when neither `--policy` nor a pattern selects one.
+pub const DEFAULT_POLICY: &str = "default";
+
+fn builtin_policies() -> Vec<(&'static str, SecretPolicy)> {
+    vec![
+        (DEFAULT_POLICY, SecretPolicy::Bytes { bytes: 32 }),
+        ("alphanumeric32", SecretPolicy::Random { alphabet: Alphabet::Alphanumeric, length: 32, characters: None }),
+        ("hex32", SecretPolicy::Random { alphabet: Alphabet::Hex, length: 32, characters: None }),
+        ("hex64", SecretPolicy::Random { alphabet: Alphabet::Hex, length: 64, characters: None }),
+        ("base64url32", SecretPolicy::Random { alphabet: Alphabet::Base64url, length: 32, characters: None }),
+        ("symbols32", SecretPolicy::Random { alphabet: Alphabet::Symbols, length: 32, characters: None }),
+        ("passphrase", SecretPolicy::Passphrase { words: 7, separator: default_separator() }),
+    ]
+}
+
+/// Built-in policies, overridden and extended by the ones in the config.
+fn policies(config: &Config) -> BTreeMap<String, SecretPolicy> {
+    let mut policies: BTreeMap<String, SecretPolicy> = builtin_policies().into_iter()
+        .map(|(name, policy)| (name.to_string(), policy))
+        .collect();
+    policies.extend(config.secret_policies.clone());
+    policies
+}
+
+pub fn find_policy(name: &str, config: &Config) -> Result<SecretPolicy> {
+    policies(config).remove(name).ok_or_else(|| anyhow!(
+        "Unknown secret policy `{name}`, available: {}",
+        policies(config).into_keys().collect::<Vec<_>>().join(", ")))
+}
+
+/// The policy mapped to `secret_name` in `secret_policy_patterns`,
+/// the longest matching pattern wins.
+pub fn policy_for_secret(secret_name: &str, config: &Config) -> Result<Option<(String, SecretPolicy)>> {
+    let mut matching = Vec::new();
+    for (pattern, policy_name) in &config.secret_policy_patterns {
+        let compiled = Pattern::new(pattern)
+            .context(format!("Invalid secret policy pattern `{pattern}`"))?;
+        if compiled.matches(secret_name) {
+            matching.push((pattern, policy_name));
+        }
+    }
+
+    match matching.into_iter().max_by_key(|(pattern, _)| pattern.len()) {
+        Some((_, policy_name)) => Ok(Some((policy_name.clone(), find_policy(policy_name, config)?))),
+        None => Ok(None),
+    }
+}
+
+pub fn prompt_policy(config: &Config) -> Result<SecretPolicy> {
+    let mut policies = policies(config);
+    let default = policies.remove(DEFAULT_POLICY).unwrap();
+    let choices = std::iter::once((DEFAULT_POLICY.to_string(), default))
+        .chain(policies)
+        .map(|(name, policy)| Choice { prompt: format!("{name} ({policy})"), choice: policy })
+        .collect();
+
+    Ok(select("How should the secret be generated?", choices)?.choice)
+}
+
+/// Resolves the policy for `secret_name`: an explicit `policy` name first,
+/// then the configured patterns, then a prompt if `prompt` is set, otherwise the default.
+pub fn resolve_policy(secret_name: &str, policy: Option<&String>, prompt: bool, config: &Config) -> Result<SecretPolicy> {
+    if let Some(policy) = policy {
+        return find_policy(policy, config);
+    }
+    if let Some((name, policy)) = policy_for_secret(secret_name, config)? {
+        eprintln!("Using secret policy `{name}` for {secret_name}");
+        return Ok(policy);
+    }
+    if prompt {
+        return prompt_policy(config);
+    }
+    find_policy(DEFAULT_POLICY, config)
+}
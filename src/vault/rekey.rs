@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use clap::{arg, ArgAction, ArgMatches, Command};
+
+use crate::core::new_password;
+use crate::vault::find_vault_files;
+use crate::vault::storage::{backups, lock_vault_file, sibling, sync_dir, write_atomically, write_staged};
+use crate::vault::vault_id::{encrypt_vault, read_password_source, VaultId, VaultIds, VaultPasswords};
+
+pub fn rekey_command() -> Command {
+    Command::new("rekey")
+        .about("Re-encrypt vault files with a new vault password, all files or none")
+        .arg(arg!(<PATH> "Vault files, globs (e.g. 'group_vars/*/vault.yml') or directories to search for vault files")
+            .action(ArgAction::Append))
+        .arg(arg!(--"new-password-file" <FILE> "File or executable with the new vault password, prompted for if missing"))
+        .arg(arg!(--"new-vault-id" <ID> "New vault ID as `label@source`, relabels all files")
+            .value_parser(VaultId::from_str)
+            .conflicts_with("new-password-file"))
+}
+
+pub fn handle_rekey_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let mut vault_files = Vec::new();
+    for path in matches.get_many::<String>("PATH").unwrap() {
+        vault_files.extend(find_vault_files(path)?);
+    }
+    vault_files.sort();
+    vault_files.dedup();
+    if vault_files.is_empty() {
+        bail!("No vault files found");
+    }
+
+    let _locks = vault_files.iter()
+        .map(|path| lock_vault_file(&path.to_string_lossy()))
+        .collect::<Result<Vec<_>>>()?;
+    let old_passwords = vault_ids.passwords()?;
+    let new_key = match (matches.get_one::<String>("new-password-file"), matches.get_one::<VaultId>("new-vault-id")) {
+        (Some(file), _) => NewKey { password: read_password_source(file, None)?, label: None },
+        (_, Some(vault_id)) => NewKey {
+            password: vault_id.resolve_new()?,
+            label: Some(vault_id.label().map(str::to_string)),
+        },
+        _ => NewKey { password: new_password("New ansible vault password:")?, label: None },
+    };
+
+    rekey(&vault_files, &old_passwords, &new_key)
+}
+
+struct NewKey {
+    password: String,
+    /// The label to apply to all files, `None` keeps the label of each file.
+    label: Option<Option<String>>,
+}
+
+struct Rekeyed {
+    path: PathBuf,
+    original: Vec<u8>,
+    encrypted: String,
+}
+
+/// Backups are rekeyed as well, so no copy of the secrets can be opened with the old password.
+/// Backups the old passwords can't decrypt, and all older ones, are removed instead.
+fn rekey(vault_files: &[PathBuf], old_passwords: &VaultPasswords, new_key: &NewKey) -> Result<()> {
+    let mut rekeyed = Vec::new();
+    let mut stale_backups = Vec::new();
+    let mut failed = 0;
+    for path in vault_files {
+        match reencrypt(path, old_passwords, new_key) {
+            Ok(file) => {
+                println!("ok      {}", path.display());
+                rekeyed.push(file);
+            }
+            Err(e) => {
+                println!("failed  {}: {:#}", path.display(), e);
+                failed += 1;
+            }
+        }
+
+        let mut backups = backups(path).into_iter();
+        for backup in backups.by_ref() {
+            match reencrypt(&backup, old_passwords, new_key) {
+                Ok(file) => rekeyed.push(file),
+                Err(_) => {
+                    stale_backups.push(backup);
+                    break;
+                }
+            }
+        }
+        stale_backups.extend(backups);
+    }
+    if failed > 0 {
+        bail!("{failed} of {} vault files could not be decrypted, nothing was changed", vault_files.len());
+    }
+
+    write_all(&rekeyed)?;
+    for backup in &stale_backups {
+        fs::remove_file(backup).context(format!("Failed to remove {}", backup.display()))?;
+        println!("removed {}, the old vault passwords can't decrypt it", backup.display());
+    }
+    println!("Rekeyed {} vault files and {} backups", vault_files.len(), rekeyed.len() - vault_files.len());
+    Ok(())
+}
+
+fn reencrypt(path: &Path, old_passwords: &VaultPasswords, new_key: &NewKey) -> Result<Rekeyed> {
+    let original = fs::read(path).context("Failed to read vault file")?;
+    let (decrypted, label) = old_passwords.decrypt_with_label(&original)
+        .context("Failed to decrypt vault file")?;
+    let label = new_key.label.clone().unwrap_or(label);
+    let encrypted = encrypt_vault(&decrypted, &new_key.password, label.as_deref())?;
+
+    Ok(Rekeyed { path: path.to_path_buf(), original, encrypted })
+}
+
+/// Stages every file as `.<name>.tdtk-rekey` next to its target before renaming any of them,
+/// and puts the original ciphertext back if a rename fails half way.
+fn write_all(rekeyed: &[Rekeyed]) -> Result<()> {
+    let staged: Vec<PathBuf> = rekeyed.iter()
+        .map(|file| sibling(&file.path, "tdtk-rekey"))
+        .collect();
+
+    let staging = rekeyed.iter().zip(&staged)
+        .try_for_each(|(file, staged)| write_staged(staged, &file.path, file.encrypted.as_bytes())
+            .with_context(|| format!("Failed to write {}", staged.display())));
+    if let Err(e) = staging {
+        staged.iter().for_each(|staged| { let _ = fs::remove_file(staged); });
+        return Err(e.context("Nothing was changed"));
+    }
+
+    for (index, (file, staged_path)) in rekeyed.iter().zip(&staged).enumerate() {
+        if let Err(e) = fs::rename(staged_path, &file.path) {
+            for file in &rekeyed[..index] {
+                let _ = write_atomically(&file.path, &file.original);
+            }
+            staged[index..].iter().for_each(|staged| { let _ = fs::remove_file(staged); });
+            return Err(e).with_context(|| format!("Failed to replace {}, restored all vault files", file.path.display()));
+        }
+    }
+
+    rekeyed.iter().try_for_each(|file| sync_dir(&file.path))
+}
@@ -0,0 +1,171 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use clap::{arg, value_parser, ArgMatches, Command};
+
+use crate::config::Config;
+use crate::vault::VAULT_HEADER;
+
+/// `.gitignore` patterns of the backups, lock files and staged writes next to vault files.
+pub const SIBLING_PATTERNS: [&str; 4] = [".*.bak.[0-9]*", ".*.tdtk-lock", ".*.tdtk-tmp", ".*.tdtk-rekey"];
+
+/// Held while a vault file is read, changed and written, so concurrent tdtk runs wait for each other.
+/// The lock file is removed again when the lock is released.
+pub struct VaultLock {
+    path: PathBuf,
+    _file: File,
+}
+
+pub fn lock_vault_file(vault_file_path: &str) -> Result<VaultLock> {
+    let path = sibling(Path::new(vault_file_path), "tdtk-lock");
+    let mut waiting = false;
+    loop {
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)
+            .context(format!("Failed to create lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                if !waiting {
+                    eprintln!("Waiting for another tdtk process to finish editing {vault_file_path}");
+                    waiting = true;
+                }
+                file.lock().context(format!("Failed to lock {vault_file_path}"))?;
+            }
+            Err(fs::TryLockError::Error(e)) => return Err(e).context(format!("Failed to lock {vault_file_path}")),
+        }
+
+        // The previous holder may have removed the lock file while we were waiting for it
+        let locked_inode = file.metadata()?.ino();
+        if fs::metadata(&path).map(|metadata| metadata.ino() == locked_inode).unwrap_or(false) {
+            return Ok(VaultLock { path, _file: file });
+        }
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Replaces the file via a synced temp file and a rename, so it is never left half written.
+/// Existing files keep their permissions.
+pub fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let temp_path = sibling(path, "tdtk-tmp");
+    let write = || -> Result<()> {
+        write_staged(&temp_path, path, content)?;
+        fs::rename(&temp_path, path)?;
+        sync_dir(path)
+    };
+
+    write().map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        e.context(format!("Failed to write {}", path.display()))
+    })
+}
+
+/// Writes and syncs the future content of `path` to `staged_path`, with the permissions of `path` if it exists.
+pub fn write_staged(staged_path: &Path, path: &Path, content: &[u8]) -> Result<()> {
+    let mut file = File::create(staged_path)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(content)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Makes a rename in the directory of `path` durable.
+pub fn sync_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Keeps the current ciphertext as `.<name>.bak.1`, older backups move up to `keep`.
+pub fn backup_vault_file(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    let _ = fs::remove_file(backup_path(path, keep));
+    for number in (1..keep).rev() {
+        let backup = backup_path(path, number);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(path, number + 1))
+                .context(format!("Failed to move backup {}", backup.display()))?;
+        }
+    }
+    let backup = backup_path(path, 1);
+    fs::copy(path, &backup).context(format!("Failed to write backup {}", backup.display()))?;
+    Ok(())
+}
+
+/// The backups of `path`, the most recent first.
+pub fn backups(path: &Path) -> Vec<PathBuf> {
+    (1..).map(|number| backup_path(path, number))
+        .take_while(|backup| backup.exists())
+        .collect()
+}
+
+fn backup_path(path: &Path, number: usize) -> PathBuf {
+    sibling(path, &format!("bak.{number}"))
+}
+
+/// `.<name>.<suffix>` next to the file, hidden so ansible doesn't load it as vars file.
+pub fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{name}.{suffix}"))
+}
+
+pub fn restore_command() -> Command {
+    Command::new("restore")
+        .about("Roll a vault file back to a backup, see `vault_backups` in the configuration")
+        .arg(arg!(<FILE> "The vault file to restore"))
+        .arg(arg!(-b --backup <NUMBER> "The backup to restore, 1 is the most recent one")
+            .value_parser(value_parser!(usize))
+            .default_value("1"))
+        .arg(arg!(-l --list "List the backups instead of restoring one")
+            .conflicts_with("backup"))
+}
+
+pub fn handle_restore_command(matches: &ArgMatches, config: &Config) -> Result<()> {
+    let vault_file_path = matches.get_one::<String>("FILE").unwrap();
+    let path = Path::new(vault_file_path);
+    let _lock = lock_vault_file(vault_file_path)?;
+
+    let backups = backups(path);
+    if backups.is_empty() {
+        bail!("There are no backups of {vault_file_path}, enable them with `vault_backups` in the configuration");
+    }
+
+    if matches.get_flag("list") {
+        for (index, backup) in backups.iter().enumerate() {
+            let modified: DateTime<Local> = fs::metadata(backup)?.modified()?.into();
+            println!("{}\t{}\t{}", index + 1, modified.format("%Y-%m-%d %H:%M:%S"), backup.display());
+        }
+        return Ok(());
+    }
+
+    let number = *matches.get_one::<usize>("backup").unwrap();
+    let backup = match backups.get(number.wrapping_sub(1)) {
+        Some(backup) => backup,
+        None => bail!("There is no backup {number} of {vault_file_path}, there are {}", backups.len()),
+    };
+    let content = fs::read(backup).context(format!("Failed to read {}", backup.display()))?;
+    if !content.starts_with(VAULT_HEADER.as_bytes()) {
+        bail!("{} is not an ansible vault", backup.display());
+    }
+
+    // The current version becomes a backup as well, so the restore can be undone
+    backup_vault_file(path, config.vault_backups.max(backups.len()))?;
+    write_atomically(path, &content)?;
+    println!("Restored {vault_file_path} from {}", backup.display());
+    Ok(())
+}
+
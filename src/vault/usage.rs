@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{arg, ArgAction, ArgMatches, Command};
+use regex::Regex;
+use walkdir::WalkDir;
+
+use crate::vault::{decrypt_vars_file, find_vault_files, is_vault_file, PREVIOUS_SUFFIX};
+use crate::vault::vault_id::VaultIds;
+
+const CODE_EXTENSIONS: [&str; 4] = ["yml", "yaml", "j2", "json"];
+const TEMPLATES_DIR: &str = "templates";
+
+pub fn usage_command() -> Command {
+    Command::new("usage")
+        .about("Report vault secrets no ansible code uses and references to secrets missing in the vault")
+        .arg(arg!([DIR] "The ansible directory with playbooks, roles, templates and vars").default_value("."))
+        .arg(arg!(--vault <PATH> "Vault files, globs or directories of the environment to check, all vault files in DIR by default")
+            .action(ArgAction::Append))
+}
+
+/// A `vault_*` variable used in a playbook, role, template or vars file.
+struct Reference {
+    file: PathBuf,
+    line: usize,
+}
+
+pub fn handle_usage_command(matches: &ArgMatches, vault_ids: &VaultIds) -> Result<()> {
+    let dir = matches.get_one::<String>("DIR").unwrap();
+    let vault_files = match matches.get_many::<String>("vault") {
+        Some(paths) => paths.map(|path| find_vault_files(path))
+            .collect::<Result<Vec<_>>>()?
+            .concat(),
+        None => find_vault_files(dir)?,
+    };
+    if vault_files.is_empty() {
+        bail!("No vault files found");
+    }
+
+    let vault = vault_ids.passwords()?;
+    let mut defined: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in &vault_files {
+        let Some(document) = decrypt_vars_file(&path.to_string_lossy(), &vault)? else {
+            eprintln!("Skipping {}, it doesn't contain YAML vars", path.display());
+            continue;
+        };
+        for key in document.values().keys().filter_map(|key| key.as_str()).filter(|key| !is_rotation_entry(key)) {
+            defined.entry(key.to_string()).or_default().push(path.clone());
+        }
+    }
+
+    let references = find_references(Path::new(dir))?;
+
+    let mut findings: Vec<(&str, &str, String)> = Vec::new();
+    for (name, paths) in &defined {
+        if !references.contains_key(name) {
+            findings.extend(paths.iter().map(|path| ("unused", name.as_str(), path.display().to_string())));
+        }
+    }
+    for (name, references) in &references {
+        if !defined.contains_key(name) {
+            findings.extend(references.iter()
+                .map(|reference| ("undefined", name.as_str(), format!("{}:{}", reference.file.display(), reference.line))));
+        }
+    }
+
+    let name_width = findings.iter().map(|(_, name, _)| name.len()).max().unwrap_or(0);
+    for (finding, name, location) in &findings {
+        println!("{finding:9}  {name:name_width$}  {location}");
+    }
+    if !findings.is_empty() {
+        bail!("{} unused or undefined vault variables in {} vault files", findings.len(), vault_files.len());
+    }
+    println!("All {} vault variables are used and defined", defined.len());
+    Ok(())
+}
+
+/// Finds `vault_*` variables in ansible code, skipping vault files, hidden directories and
+/// the keys of plain vars files, which define variables instead of using them.
+fn find_references(dir: &Path) -> Result<BTreeMap<String, Vec<Reference>>> {
+    let variable = Regex::new(r"\bvault_[A-Za-z0-9_]+\b").unwrap();
+    let key = Regex::new(r"^\s*(?:-\s+)?(vault_[A-Za-z0-9_]+)\s*:").unwrap();
+
+    let mut references: BTreeMap<String, Vec<Reference>> = BTreeMap::new();
+    let entries = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in entries {
+        let entry = entry.context(format!("Failed to read {}", dir.display()))?;
+        if !entry.file_type().is_file() || !is_code(entry.path()) || is_vault_file(entry.path()) {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(entry.path()) else { continue };
+
+        for (index, line) in content.lines().enumerate() {
+            let defined_key = key.captures(line).map(|captures| captures.get(1).unwrap().range());
+            let names: BTreeSet<&str> = variable.find_iter(line)
+                .filter(|found| Some(found.range()) != defined_key)
+                .map(|found| found.as_str())
+                .collect();
+            for name in names {
+                references.entry(name.to_string()).or_default()
+                    .push(Reference { file: entry.path().to_path_buf(), line: index + 1 });
+            }
+        }
+    }
+    Ok(references)
+}
+
+/// The previous secret of a rotation is kept for the grace period only, not referenced.
+fn is_rotation_entry(key: &str) -> bool {
+    key.ends_with(PREVIOUS_SUFFIX)
+}
+
+fn is_code(path: &Path) -> bool {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+    CODE_EXTENSIONS.contains(&extension.as_str())
+        || path.components().any(|component| component.as_os_str() == TEMPLATES_DIR)
+}
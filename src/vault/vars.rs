@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+
+use crate::vault::storage::write_atomically;
+use crate::vault::yaml_edit::set_entry;
+
+const DEFAULT_VARS_FILE: &str = "vars.yml";
+
+/// Pairs `vault_foo` in a vault file with `foo: "{{ vault_foo }}"` in the plain vars file next to it,
+/// the ansible best practice to keep vault variables discoverable. An existing `foo` is left alone.
+pub fn add_vars_indirection(secret_name: &str, vault_file_path: &str) -> Result<()> {
+    let vault_name = secret_name.split('.').next().unwrap();
+    let name = vault_name.strip_prefix("vault_").unwrap_or(vault_name);
+    let vars_file_path = vars_file_path(Path::new(vault_file_path));
+    let expected = format!("{{{{ {vault_name} }}}}");
+
+    let content = match fs::read_to_string(&vars_file_path) {
+        Ok(content) => content,
+        Err(_) => "---\n".to_string(),
+    };
+    let vars: Value = serde_yaml::from_str(&content)
+        .context(format!("Failed to parse {}", vars_file_path.display()))?;
+    match vars.get(name) {
+        Some(Value::String(value)) if value.replace(' ', "") == expected.replace(' ', "") => {
+            println!("`{name}` in {} already refers to {vault_name}", vars_file_path.display());
+            return Ok(());
+        }
+        Some(_) => {
+            eprintln!("`{name}` is already defined in {}, not changing it", vars_file_path.display());
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let entry = format!("{name}: \"{expected}\"\n");
+    write_atomically(&vars_file_path, set_entry(&content, name, &entry).as_bytes())?;
+    println!("Added `{}` to {}", entry.trim_end(), vars_file_path.display());
+    Ok(())
+}
+
+/// `vault.yml` pairs with `vars.yml`, `vault.yaml` with `vars.yaml`, anything else with `vars.yml`.
+fn vars_file_path(vault_file_path: &Path) -> PathBuf {
+    let vault_file_name = vault_file_path.file_name().unwrap_or_default().to_string_lossy();
+    let vars_file_name = match vault_file_name.strip_prefix("vault") {
+        Some(rest) if rest.starts_with('.') => format!("vars{rest}"),
+        _ => DEFAULT_VARS_FILE.to_string(),
+    };
+    vault_file_path.with_file_name(vars_file_name)
+}
@@ -0,0 +1,435 @@
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{arg, Arg, ArgAction, ArgMatches};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::core::{capture_command, new_password, password};
+use crate::vault::agent::{agent_password, store_agent_password};
+use crate::vault::ansible_cfg::{AnsibleConfig, expand_home};
+
+const VAULT_1_1_HEADER: &str = "$ANSIBLE_VAULT;1.1;AES256";
+const VAULT_1_2_HEADER: &str = "$ANSIBLE_VAULT;1.2;AES256";
+const DEFAULT_LABEL: &str = "default";
+pub const PROMPT_SOURCE: &str = "prompt";
+const MIN_PASSWORD_LENGTH: usize = 4;
+
+/// A `--vault-id label@source` argument, the label is optional like in ansible.
+#[derive(Debug, Clone)]
+pub struct VaultId {
+    label: Option<String>,
+    source: String,
+}
+
+impl FromStr for VaultId {
+    type Err = anyhow::Error;
+
+    fn from_str(vault_id: &str) -> Result<Self> {
+        let (label, source) = match vault_id.split_once('@') {
+            Some((label, source)) => (normalize_label(label), source),
+            None => (None, vault_id),
+        };
+        if source.is_empty() {
+            bail!("Missing password source in vault ID `{vault_id}`");
+        }
+        Ok(VaultId { label, source: source.to_string() })
+    }
+}
+
+/// The vault IDs given on the command line, resolved to passwords on first use.
+#[derive(Debug, Clone, Default)]
+pub struct VaultIds {
+    ids: Vec<VaultId>,
+    encrypt_label: Option<String>,
+}
+
+pub fn vault_id_args() -> [Arg; 2] {
+    [
+        arg!(--"vault-id" <ID> "Vault ID as `label@source`, source is `prompt`, a password file or a client script")
+            .value_parser(VaultId::from_str)
+            .action(ArgAction::Append)
+            .global(true),
+        arg!(--"encrypt-vault-id" <LABEL> "Label of the vault ID used to encrypt new vault files")
+            .global(true),
+    ]
+}
+
+impl VaultIds {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        VaultIds {
+            ids: matches.get_many::<VaultId>("vault-id").unwrap_or_default().cloned().collect(),
+            encrypt_label: matches.get_one::<String>("encrypt-vault-id").cloned(),
+        }
+    }
+
+    /// The command line arguments to pass the same vault IDs on to another tdtk call.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for id in &self.ids {
+            args.push("--vault-id".to_string());
+            args.push(match &id.label {
+                Some(label) => format!("{label}@{}", id.source),
+                None => id.source.clone(),
+            });
+        }
+        if let Some(encrypt_label) = &self.encrypt_label {
+            args.push("--encrypt-vault-id".to_string());
+            args.push(encrypt_label.clone());
+        }
+        args
+    }
+
+    /// Resolves every vault ID to its password, from the vault agent if one is running.
+    /// Without vault IDs they come from the environment or `ansible.cfg` like in ansible, or the password is prompted for.
+    pub fn passwords(&self) -> Result<VaultPasswords> {
+        let configured = if self.ids.is_empty() { ConfiguredVaultIds::load()? } else { ConfiguredVaultIds::default() };
+        let ids = if self.ids.is_empty() { &configured.ids } else { &self.ids };
+        let passwords = if ids.is_empty() {
+            vec![VaultPassword::new(None)?]
+        } else {
+            ids.iter().map(|id| VaultPassword::new(Some(id.clone()))).collect::<Result<_>>()?
+        };
+
+        let encrypt_with = match self.encrypt_label.as_ref().or(configured.encrypt_label.as_ref()) {
+            Some(encrypt_label) => {
+                let label = normalize_label(encrypt_label);
+                let index = passwords.iter().position(|p: &VaultPassword| p.label == label)
+                    .with_context(|| format!("No vault ID with label `{encrypt_label}` given for --encrypt-vault-id"))?;
+                Some(index)
+            }
+            None if passwords.len() == 1 => Some(0),
+            None => None,
+        };
+
+        Ok(VaultPasswords { passwords, encrypt_with })
+    }
+}
+
+impl VaultId {
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Resolves a vault ID for a new password, prompts ask for a confirmation.
+    pub fn resolve_new(&self) -> Result<String> {
+        if self.source == PROMPT_SOURCE {
+            let label = self.label.as_deref().unwrap_or(DEFAULT_LABEL);
+            return new_password(&format!("New vault password ({label}):"));
+        }
+        self.resolve()
+    }
+
+    fn resolve(&self) -> Result<String> {
+        let label = self.label.as_deref().unwrap_or(DEFAULT_LABEL);
+        let vault_password = if self.source == PROMPT_SOURCE {
+            prompt_password(&format!("Vault password ({label}):"))?
+        } else {
+            read_password_source(&self.source, self.label.as_deref())?
+        };
+        check_length(&vault_password, &self.source)?;
+        Ok(vault_password)
+    }
+
+    /// Password files in `ansible.cfg` are relative to it, in environment variables to the working directory.
+    fn relative_to(self, ansible_config: Option<&AnsibleConfig>) -> Self {
+        if self.source == PROMPT_SOURCE {
+            return self;
+        }
+        let source = match ansible_config {
+            Some(ansible_config) => ansible_config.resolve(&self.source),
+            None => expand_home(&self.source),
+        };
+        VaultId { label: self.label, source: source.to_string_lossy().to_string() }
+    }
+}
+
+/// The vault IDs ansible uses without `--vault-id`, environment variables win over `ansible.cfg`.
+#[derive(Default)]
+struct ConfiguredVaultIds {
+    ids: Vec<VaultId>,
+    encrypt_label: Option<String>,
+}
+
+impl ConfiguredVaultIds {
+    fn load() -> Result<Self> {
+        let ansible_config = AnsibleConfig::load()?;
+        let setting = |env: &str, key: &str| -> Option<(String, Option<&AnsibleConfig>)> {
+            match std::env::var(env) {
+                Ok(value) if !value.trim().is_empty() => Some((value, None)),
+                _ => ansible_config.as_ref().and_then(|config| Some((config.get(key)?.to_string(), Some(config)))),
+            }
+        };
+
+        let mut ids = Vec::new();
+        if let Some((identity_list, config)) = setting("ANSIBLE_VAULT_IDENTITY_LIST", "vault_identity_list") {
+            for vault_id in identity_list.split(',').map(str::trim).filter(|vault_id| !vault_id.is_empty()) {
+                ids.push(VaultId::from_str(vault_id)?.relative_to(config));
+            }
+        }
+        if let Some((password_file, config)) = setting("ANSIBLE_VAULT_PASSWORD_FILE", "vault_password_file") {
+            if config.is_none() {
+                eprintln!("Using ANSIBLE_VAULT_PASSWORD_FILE environment variable");
+            }
+            let label = setting("ANSIBLE_VAULT_IDENTITY", "vault_identity").and_then(|(label, _)| normalize_label(&label));
+            ids.push(VaultId { label, source: password_file }.relative_to(config));
+        }
+        let encrypt_label = setting("ANSIBLE_VAULT_ENCRYPT_IDENTITY", "vault_encrypt_identity").map(|(label, _)| label);
+
+        Ok(ConfiguredVaultIds { ids, encrypt_label })
+    }
+}
+
+fn prompt_password(prompt: &str) -> Result<String> {
+    let vault_password = password(prompt)?;
+    if vault_password.len() < MIN_PASSWORD_LENGTH {
+        println!("The vault password must be at least {MIN_PASSWORD_LENGTH} characters long");
+        prompt_password(prompt)
+    } else {
+        Ok(vault_password)
+    }
+}
+
+fn check_length(vault_password: &str, source: &str) -> Result<()> {
+    if vault_password.len() < MIN_PASSWORD_LENGTH {
+        bail!("The vault password from `{source}` must be at least {MIN_PASSWORD_LENGTH} characters long");
+    }
+    Ok(())
+}
+
+/// Reads a password file the way ansible does: client scripts (`*-client`, `*-client.py`, ...)
+/// get `--vault-id <label>`, other executables are run without arguments,
+/// everything else is read as a plain password file.
+pub fn read_password_source(source: &str, label: Option<&str>) -> Result<String> {
+    let path = Path::new(source);
+    let metadata = fs::metadata(path)
+        .context(format!("Failed to read vault password file `{source}`"))?;
+
+    let vault_password = if metadata.permissions().mode() & 0o111 != 0 {
+        if is_client_script(path) {
+            capture_command(source, &["--vault-id", label.unwrap_or(DEFAULT_LABEL)], None)?
+        } else {
+            capture_command(source, &[], None)?
+        }
+    } else {
+        fs::read_to_string(path)
+            .context(format!("Failed to read vault password file `{source}`"))?
+    };
+
+    Ok(vault_password.trim().to_string())
+}
+
+fn is_client_script(path: &Path) -> bool {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().ends_with("-client"))
+        .unwrap_or(false)
+}
+
+fn normalize_label(label: &str) -> Option<String> {
+    let label = label.trim();
+    if label.is_empty() || label == DEFAULT_LABEL { None } else { Some(label.to_string()) }
+}
+
+struct VaultPassword {
+    label: Option<String>,
+    password: RefCell<String>,
+    /// Where the password comes from if the agent doesn't know it, prompted for without a vault ID.
+    source: Option<VaultId>,
+    /// The agent keeps passwords per label and source, the absolute password file path or `prompt`.
+    agent_source: String,
+    /// A password from the agent that has not decrypted anything yet, it may be outdated.
+    unverified: Cell<bool>,
+    /// Whether the agent has the password, it only gets passwords that decrypted a vault.
+    in_agent: Cell<bool>,
+}
+
+impl VaultPassword {
+    /// Asks a running vault agent first, then the source of the vault ID.
+    fn new(source: Option<VaultId>) -> Result<Self> {
+        let label = source.as_ref().and_then(|id| id.label.clone());
+        let agent_source = match &source {
+            Some(id) if id.source != PROMPT_SOURCE => fs::canonicalize(&id.source)
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_else(|_| id.source.clone()),
+            _ => PROMPT_SOURCE.to_string(),
+        };
+        let (password, from_agent) = match agent_password(label.as_deref(), &agent_source) {
+            Some(password) => (password, true),
+            None => (resolve_source(source.as_ref())?, false),
+        };
+        Ok(VaultPassword {
+            label,
+            password: RefCell::new(password),
+            source,
+            agent_source,
+            unverified: Cell::new(from_agent),
+            in_agent: Cell::new(from_agent),
+        })
+    }
+
+    /// With `refresh`, a failing password from the agent is replaced by the one from the source and tried again.
+    /// A password that decrypts is handed to the agent.
+    fn decrypt(&self, payload: &str, refresh: bool) -> Result<Vec<u8>> {
+        let mut decrypted = ansible_vault::decrypt(payload.as_bytes(), &self.password.borrow());
+        if decrypted.is_err() && refresh && self.unverified.get() {
+            self.refresh()?;
+            decrypted = ansible_vault::decrypt(payload.as_bytes(), &self.password.borrow());
+        }
+        let decrypted = decrypted.map_err(|e| anyhow!(e.to_string()))?;
+
+        self.unverified.set(false);
+        if !self.in_agent.replace(true) {
+            store_agent_password(self.label.as_deref(), &self.agent_source, &self.password.borrow());
+        }
+        Ok(decrypted)
+    }
+
+    /// New ciphertext is only written with a password from the source, or one from the agent that decrypted a vault.
+    fn verify(&self) -> Result<()> {
+        if self.unverified.get() {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn refresh(&self) -> Result<()> {
+        let password = resolve_source(self.source.as_ref())?;
+        self.unverified.set(false);
+        self.in_agent.set(false);
+        *self.password.borrow_mut() = password;
+        Ok(())
+    }
+}
+
+fn resolve_source(source: Option<&VaultId>) -> Result<String> {
+    match source {
+        Some(id) => id.resolve(),
+        None => prompt_password("Ansible vault password:"),
+    }
+}
+
+/// The passwords of all given vault IDs.
+pub struct VaultPasswords {
+    passwords: Vec<VaultPassword>,
+    encrypt_with: Option<usize>,
+}
+
+impl VaultPasswords {
+    pub fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.decrypt_with_label(content)?.0)
+    }
+
+    /// Decrypts vault content with the password matching its label first, then all others,
+    /// and returns the label of the password that worked.
+    pub fn decrypt_with_label(&self, content: &[u8]) -> Result<(Vec<u8>, Option<String>)> {
+        let (label, payload) = parse_vault(content)?;
+        let candidates = self.passwords.iter().filter(|p| p.label == label)
+            .chain(self.passwords.iter().filter(|p| p.label != label));
+
+        // Only the password meant for the vault is refreshed, other vault IDs are expected to fail
+        let single = self.passwords.len() == 1;
+        let mut last_error = None;
+        for candidate in candidates {
+            match candidate.decrypt(&payload, single || candidate.label == label) {
+                Ok(decrypted) => return Ok((decrypted, candidate.label.clone())),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let label = label.as_deref().unwrap_or(DEFAULT_LABEL);
+        Err(anyhow!(last_error.map(|e| e.to_string()).unwrap_or_default()))
+            .context(format!("No vault password for vault ID `{label}` could decrypt the vault"))
+    }
+
+    pub fn decrypt_file(&self, path: &str) -> Result<Vec<u8>> {
+        let content = fs::read(path).context(format!("Failed to read {path}"))?;
+        self.decrypt(&content)
+    }
+
+    /// Encrypts content for an existing vault file with the vault ID that decrypts it,
+    /// or for a new file with the encrypt vault ID.
+    pub fn encrypt_for_file(&self, path: &str, plaintext: &[u8]) -> Result<String> {
+        match fs::read(path) {
+            Ok(content) if content.starts_with(b"$ANSIBLE_VAULT;") => self.encrypt_like(Some(&content), plaintext),
+            _ => self.encrypt(plaintext),
+        }
+    }
+
+    /// Encrypts with the vault ID that decrypts `existing` vault content, or the encrypt vault ID without it.
+    pub fn encrypt_like(&self, existing: Option<&[u8]>, plaintext: &[u8]) -> Result<String> {
+        match existing {
+            Some(existing) => {
+                let (_, label) = self.decrypt_with_label(existing)?;
+                self.encrypt_with_label(plaintext, label)
+            }
+            None => self.encrypt(plaintext),
+        }
+    }
+
+    /// Encrypts content for a new vault file.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let vault_password = match self.encrypt_with {
+            Some(index) => &self.passwords[index],
+            None => bail!("Multiple vault IDs given, choose the one to encrypt with using --encrypt-vault-id"),
+        };
+        vault_password.verify()?;
+        encrypt_vault(plaintext, &vault_password.password.borrow(), vault_password.label.as_deref())
+    }
+
+    /// A short fingerprint of a secret keyed by the password of the vault ID with `label`,
+    /// so it can't be checked against guessed secrets without the password.
+    pub fn fingerprint(&self, label: Option<&str>, secret: &str) -> Result<String> {
+        let vault_password = self.passwords.iter()
+            .find(|p| p.label.as_deref() == label)
+            .with_context(|| format!("No vault password for vault ID `{}`", label.unwrap_or(DEFAULT_LABEL)))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(vault_password.password.borrow().as_bytes())
+            .context("Failed to derive the fingerprint key")?;
+        mac.update(secret.as_bytes());
+        Ok(format!("HMAC-SHA256:{}", hex::encode(&mac.finalize().into_bytes()[..8])))
+    }
+
+    fn encrypt_with_label(&self, plaintext: &[u8], label: Option<String>) -> Result<String> {
+        let vault_password = self.passwords.iter()
+            .find(|p| p.label == label)
+            .with_context(|| format!("No vault password for vault ID `{}`", label.as_deref().unwrap_or(DEFAULT_LABEL)))?;
+        encrypt_vault(plaintext, &vault_password.password.borrow(), label.as_deref())
+    }
+}
+
+/// Encrypts to the ansible vault format, 1.2 with a label, 1.1 without.
+pub fn encrypt_vault(plaintext: &[u8], password: &str, label: Option<&str>) -> Result<String> {
+    let payload = ansible_vault::encrypt(plaintext, password)
+        .context("Failed to encrypt vault")?;
+
+    let mut vault = match label {
+        Some(label) => format!("{VAULT_1_2_HEADER};{label}\n"),
+        None => format!("{VAULT_1_1_HEADER}\n"),
+    };
+    for line in payload.as_bytes().chunks(80) {
+        vault.push_str(std::str::from_utf8(line).unwrap());
+        vault.push('\n');
+    }
+    Ok(vault)
+}
+
+/// Splits vault content into its label and hex payload.
+pub fn parse_vault(content: &[u8]) -> Result<(Option<String>, String)> {
+    let content = std::str::from_utf8(content).context("Not an ansible vault, UTF-8 content expected")?;
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or_default().trim();
+
+    let label = if header == VAULT_1_1_HEADER {
+        None
+    } else if let Some(label) = header.strip_prefix(VAULT_1_2_HEADER).and_then(|rest| rest.strip_prefix(';')) {
+        normalize_label(label)
+    } else {
+        bail!("Not an ansible vault, unsupported header `{header}`");
+    };
+
+    let payload: String = lines.map(str::trim).collect();
+    Ok((label, payload))
+}
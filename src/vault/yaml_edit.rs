@@ -0,0 +1,409 @@
+use std::ops::Range;
+
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+
+/// Indentation serde_yaml uses for nested levels.
+const RENDERED_INDENT: usize = 2;
+
+/// Line range of the top-level `key` entry, including its indented value lines.
+pub fn find_entry(content: &str, key: &str) -> Option<Range<usize>> {
+    find_path(content, &[key.to_string()])
+}
+
+/// Replaces the top-level `key` entry with `entry`, or appends `entry` if `key` is missing.
+/// Every other line is kept as is.
+pub fn set_entry(content: &str, key: &str, entry: &str) -> String {
+    let entry = entry.trim_end_matches('\n');
+    let mut lines: Vec<&str> = content.lines().collect();
+
+    match find_entry(content, key) {
+        Some(range) => {
+            lines.splice(range, entry.lines());
+        }
+        None => {
+            trim_document_end(&mut lines);
+            lines.extend(entry.lines());
+        }
+    }
+
+    join(&lines)
+}
+
+/// Line range of the entry at `path` in a block style YAML mapping, including its value lines.
+pub fn find_path(content: &str, path: &[String]) -> Option<Range<usize>> {
+    let lines: Vec<&str> = content.lines().collect();
+    find_in(&lines, 0..lines.len(), 0, path)
+}
+
+fn find_in(lines: &[&str], range: Range<usize>, indent: usize, path: &[String]) -> Option<Range<usize>> {
+    let (key, children) = path.split_first()?;
+    let start = range.clone()
+        .find(|index| key_length(lines[*index], indent, key).is_some())?;
+    let entry = start..entry_end(lines, start, range.end, indent);
+
+    if children.is_empty() {
+        return Some(entry);
+    }
+    let child_indent = child_indent(lines, &entry)?;
+    find_in(lines, entry.start + 1..entry.end, child_indent, children)
+}
+
+/// Length of `key:` if the line at `indent` is the entry of `key`.
+fn key_length(line: &str, indent: usize, key: &str) -> Option<usize> {
+    if indentation(line) != indent {
+        return None;
+    }
+    let key_pattern = Regex::new(&format!(
+        r#"^(?:{key}|"{key}"|'{key}')\s*:"#,
+        key = regex::escape(key),
+    )).unwrap();
+    let rest = &line[indent..];
+    let length = key_pattern.find(rest)?.end();
+    match rest[length..].chars().next() {
+        None | Some(' ') | Some('\t') => Some(length),
+        _ => None,
+    }
+}
+
+/// The entry ends before the next line at its indentation or less, trailing blank lines are not part of it.
+fn entry_end(lines: &[&str], start: usize, limit: usize, indent: usize) -> usize {
+    let mut end = start + 1;
+    for (index, line) in lines.iter().enumerate().take(limit).skip(start + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_indent = indentation(line);
+        let is_sequence_item = line_indent == indent && (line[indent..] == *"-" || line[indent..].starts_with("- "));
+        if line_indent <= indent && !is_sequence_item {
+            break;
+        }
+        end = index + 1;
+    }
+    end
+}
+
+/// Indentation of the first value line of a block mapping entry.
+fn child_indent(lines: &[&str], entry: &Range<usize>) -> Option<usize> {
+    let indent = indentation(lines[entry.start]);
+    lines[entry.start + 1..entry.end].iter()
+        .find(|line| !is_blank_or_comment(line))
+        .map(|line| indentation(line))
+        .filter(|child_indent| *child_indent > indent)
+}
+
+/// Whether the entry line of `key` has no inline value, so its value lines follow as a block.
+fn has_block_value(line: &str, key: &str) -> bool {
+    let indent = indentation(line);
+    key_length(line, indent, key)
+        .map(|length| line[indent + length..].trim())
+        .map(|value| value.is_empty() || value.starts_with('#'))
+        .unwrap_or(false)
+}
+
+/// Sets `path` to `value`, keeping every unrelated line as is and the comment after a replaced key.
+/// Missing parent mappings are created.
+/// Returns `None` if the YAML can't be edited in place, e.g. for flow style mappings.
+pub fn set_path(content: &str, path: &[String], value: &Value) -> Option<String> {
+    let mut lines: Vec<&str> = content.lines().collect();
+
+    if let Some(range) = find_path(content, path) {
+        let indent = indentation(lines[range.start]);
+        let comment = trailing_comment(lines[range.start]).unwrap_or_default();
+        let entry = render_entry(&path[path.len() - 1..], value, indent, indent_unit(&lines))?;
+        let mut entry_lines: Vec<String> = entry.lines().map(str::to_string).collect();
+        entry_lines[0].push_str(comment);
+        lines.splice(range, entry_lines.iter().map(String::as_str));
+        return Some(join(&lines));
+    }
+
+    let parent_length = (1..path.len()).rev()
+        .find(|length| find_path(content, &path[..*length]).is_some())
+        .unwrap_or(0);
+    if parent_length == 0 {
+        let rendered = render_entry(path, value, 0, indent_unit(&lines))?;
+        trim_document_end(&mut lines);
+        lines.extend(rendered.lines());
+        return Some(join(&lines));
+    }
+
+    let entry = find_path(content, &path[..parent_length])?;
+    if !has_block_value(lines[entry.start], &path[parent_length - 1]) {
+        return None;
+    }
+    let unit = indent_unit(&lines);
+    let indent = child_indent(&lines, &entry).unwrap_or(indentation(lines[entry.start]) + unit);
+    let rendered = render_entry(&path[parent_length..], value, indent, unit)?;
+    lines.splice(entry.end..entry.end, rendered.lines());
+    Some(join(&lines))
+}
+
+/// Removes the entry at `path` together with the comment lines right above it.
+pub fn remove_path(content: &str, path: &[String]) -> Option<String> {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let range = find_path(content, path)?;
+
+    let indent = indentation(lines[range.start]);
+    let mut start = range.start;
+    while start > 0 && is_comment(lines[start - 1]) && indentation(lines[start - 1]) == indent {
+        start -= 1;
+    }
+    lines.drain(start..range.end);
+    let mut content = join(&lines);
+
+    // An emptied parent mapping stays a mapping instead of becoming null
+    if path.len() > 1 {
+        let parent = find_path(&content, &path[..path.len() - 1])?;
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        if lines[parent.start + 1..parent.end].iter().all(|line| is_blank_or_comment(line)) {
+            lines[parent.start].push_str(" {}");
+            content = lines.join("\n") + "\n";
+        }
+    }
+    Some(content)
+}
+
+/// Renames the last segment of `path` to `new_key`, the value and comments stay untouched.
+pub fn rename_path(content: &str, path: &[String], new_key: &str) -> Option<String> {
+    let range = find_path(content, path)?;
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    let line = &lines[range.start];
+    let indent = indentation(line);
+    let length = key_length(line, indent, &path[path.len() - 1])?;
+    let key = serde_yaml::to_string(&Value::String(new_key.to_string())).ok()?;
+    lines[range.start] = format!("{}{}:{}", &line[..indent], key.trim_end(), &line[indent + length..]);
+
+    Some(lines.join("\n") + "\n")
+}
+
+/// Renders `path: value` as nested block mappings at `indent`, nested levels are indented by `unit`.
+/// The content lines of block scalars move with their key, so their own indentation is kept.
+fn render_entry(path: &[String], value: &Value, indent: usize, unit: usize) -> Option<String> {
+    let entry = path.iter().rev().fold(value.clone(), |value, key| {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::String(key.clone()), value);
+        Value::Mapping(mapping)
+    });
+    let rendered = serde_yaml::to_string(&entry).ok()?;
+    let block_scalar_header = Regex::new(r"(?:^-|:)\s+[|>][1-9+-]*$").unwrap();
+
+    let mut lines = Vec::new();
+    // Original and new indentation of the key of the block scalar being rendered
+    let mut block_scalar: Option<(usize, usize)> = None;
+    for line in rendered.lines() {
+        let line_indent = indentation(line);
+        match block_scalar {
+            _ if line.is_empty() => {
+                lines.push(String::new());
+                continue;
+            }
+            Some((key_indent, new_key_indent)) if line_indent > key_indent => {
+                lines.push(format!("{}{}", " ".repeat(line_indent - key_indent + new_key_indent), &line[line_indent..]));
+                continue;
+            }
+            _ => {}
+        }
+
+        let new_indent = indent + line_indent / RENDERED_INDENT * unit;
+        lines.push(format!("{}{}", " ".repeat(new_indent), &line[line_indent..]));
+        block_scalar = block_scalar_header.is_match(line.trim()).then_some((line_indent, new_indent));
+    }
+    Some(lines.join("\n"))
+}
+
+/// The indentation step the document uses, the smallest indentation of any value line.
+fn indent_unit(lines: &[&str]) -> usize {
+    lines.iter()
+        .filter(|line| !is_blank_or_comment(line))
+        .map(|line| indentation(line))
+        .filter(|indent| *indent > 0)
+        .min()
+        .unwrap_or(RENDERED_INDENT)
+}
+
+fn trim_document_end(lines: &mut Vec<&str>) {
+    while lines.last().map(|line| line.trim() == "...").unwrap_or(false) {
+        lines.pop();
+    }
+}
+
+fn join(lines: &[&str]) -> String {
+    lines.join("\n") + "\n"
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// The `# comment` at the end of a line with the whitespace before it, `#` inside quotes doesn't start one.
+fn trailing_comment(line: &str) -> Option<&str> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                previous = c;
+                continue;
+            }
+            (Some(open), _) if c == open && !escaped => quote = None,
+            (None, '"' | '\'') if previous.is_whitespace() => quote = Some(c),
+            (None, '#') if previous.is_whitespace() => {
+                let start = line[..index].trim_end().len();
+                return Some(&line[start..]);
+            }
+            _ => {}
+        }
+        escaped = false;
+        previous = c;
+    }
+    None
+}
+
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    line.trim().is_empty() || is_comment(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('.').map(str::to_string).collect()
+    }
+
+    fn string(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    fn parse(content: &str) -> Value {
+        serde_yaml::from_str(content).unwrap()
+    }
+
+    const DOCUMENT: &str = "\
+---
+# database
+vault_db:
+  # the primary
+  primary:
+    password: old
+  replica: {password: replica}
+vault_token: 'abc' # rotated monthly
+";
+
+    const FOUR_SPACES: &str = "\
+---
+# database
+vault_db:
+    primary:
+        password: old
+vault_token: \"abc\"
+";
+
+    #[test]
+    fn set_path_replaces_a_nested_value() {
+        let edited = set_path(DOCUMENT, &path("vault_db.primary.password"), &string("new")).unwrap();
+        assert_eq!(edited, DOCUMENT.replace("password: old", "password: new"));
+    }
+
+    #[test]
+    fn set_path_adds_to_an_existing_parent() {
+        let edited = set_path(DOCUMENT, &path("vault_db.primary.user"), &string("admin")).unwrap();
+        assert_eq!(edited, DOCUMENT.replace("    password: old\n", "    password: old\n    user: admin\n"));
+    }
+
+    #[test]
+    fn set_path_creates_missing_parents() {
+        let edited = set_path(DOCUMENT, &path("vault_api.key"), &string("secret")).unwrap();
+        assert_eq!(edited, format!("{DOCUMENT}vault_api:\n  key: secret\n"));
+    }
+
+    #[test]
+    fn set_path_uses_the_indentation_of_the_document() {
+        let edited = set_path(FOUR_SPACES, &path("vault_db.replica.password"), &string("secret")).unwrap();
+        assert_eq!(edited, FOUR_SPACES.replace(
+            "vault_token",
+            "    replica:\n        password: secret\nvault_token",
+        ));
+    }
+
+    #[test]
+    fn set_path_keeps_the_indentation_inside_block_scalars() {
+        let edited = set_path(FOUR_SPACES, &path("vault_db.pem"), &string("l1\n  l2\nl3")).unwrap();
+        assert_eq!(edited, FOUR_SPACES.replace(
+            "vault_token",
+            "    pem: |-\n      l1\n        l2\n      l3\nvault_token",
+        ));
+        assert_eq!(parse(&edited)["vault_db"]["pem"], string("l1\n  l2\nl3"));
+    }
+
+    #[test]
+    fn set_path_replaces_a_block_scalar() {
+        let content = "vault_key: |\n  line 1\n    line 2\nvault_other: x\n";
+        let edited = set_path(content, &path("vault_key"), &string("new\n")).unwrap();
+        assert_eq!(edited, "vault_key: |\n  new\nvault_other: x\n");
+    }
+
+    #[test]
+    fn set_path_keeps_the_comment_after_the_key() {
+        let content = "vault_a: one   # used by the billing service\nvault_b: 'x # y' # quoted\n";
+        let edited = set_path(content, &path("vault_a"), &string("uno")).unwrap();
+        assert_eq!(edited, "vault_a: uno   # used by the billing service\nvault_b: 'x # y' # quoted\n");
+        let edited = set_path(&edited, &path("vault_b"), &string("z")).unwrap();
+        assert_eq!(edited, "vault_a: uno   # used by the billing service\nvault_b: z # quoted\n");
+
+        let edited = set_path(DOCUMENT, &path("vault_token"), &string("l1\nl2")).unwrap();
+        assert_eq!(edited, DOCUMENT.replace("vault_token: 'abc' # rotated monthly", "vault_token: |- # rotated monthly\n  l1\n  l2"));
+        assert_eq!(parse(&edited)["vault_token"], string("l1\nl2"));
+    }
+
+    #[test]
+    fn set_path_does_not_edit_flow_mappings() {
+        assert_eq!(set_path(DOCUMENT, &path("vault_db.replica.user"), &string("admin")), None);
+        let edited = set_path(DOCUMENT, &path("vault_db.replica"), &parse("{password: replica, user: admin}")).unwrap();
+        assert_eq!(edited, DOCUMENT.replace(
+            "  replica: {password: replica}\n",
+            "  replica:\n    password: replica\n    user: admin\n",
+        ));
+    }
+
+    #[test]
+    fn remove_path_removes_the_comments_above() {
+        let edited = remove_path(DOCUMENT, &path("vault_db.primary")).unwrap();
+        assert_eq!(edited, DOCUMENT.replace("  # the primary\n  primary:\n    password: old\n", ""));
+    }
+
+    #[test]
+    fn remove_path_keeps_an_emptied_parent_a_mapping() {
+        let edited = remove_path(FOUR_SPACES, &path("vault_db.primary.password")).unwrap();
+        assert_eq!(edited, FOUR_SPACES.replace("    primary:\n        password: old\n", "    primary: {}\n"));
+    }
+
+    #[test]
+    fn remove_path_removes_block_scalars() {
+        let content = "vault_key: |\n  line 1\n\n    line 2\nvault_other: x\n";
+        assert_eq!(remove_path(content, &path("vault_key")).unwrap(), "vault_other: x\n");
+    }
+
+    #[test]
+    fn rename_path_keeps_value_and_comments() {
+        let edited = rename_path(DOCUMENT, &path("vault_token"), "vault_api_token").unwrap();
+        assert_eq!(edited, DOCUMENT.replace("vault_token:", "vault_api_token:"));
+
+        let edited = rename_path(FOUR_SPACES, &path("vault_db.primary"), "main").unwrap();
+        assert_eq!(edited, FOUR_SPACES.replace("primary:", "main:"));
+    }
+
+    #[test]
+    fn missing_paths_are_not_found() {
+        assert_eq!(remove_path(DOCUMENT, &path("vault_db.missing")), None);
+        assert_eq!(rename_path(DOCUMENT, &path("vault_missing"), "vault_new"), None);
+        assert_eq!(find_path(DOCUMENT, &path("vault_db.primary.password.deeper")), None);
+    }
+}